@@ -1,10 +1,16 @@
-use crate::memory::{Mem, MemRead, MemWrite};
+use crate::memory::{Mem, MemRead, MemWrite, Pointer, POINTER_WIDTH};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::mem::size_of;
 
+/// The size of a box: a tag, followed by a pointer.
+pub const BOX_SIZE: usize = 1 + POINTER_WIDTH;
+
 /// Represents an object that can be written to and read from our
 /// memory. Such objects have no "semantics" associated with them. That
 /// is to say, a `Pair` may represent a value, or part of a closure, or
 /// part of an environment.
+#[derive(Debug, Clone, PartialEq)]
 pub enum SchemeObj {
     Nil,
     Bool(bool),
@@ -14,6 +20,64 @@ pub enum SchemeObj {
         car: Box<SchemeObj>,
         cdr: Box<SchemeObj>,
     },
+    Eof,
+    Unspecified,
+    /// An exact fraction. Rationals should be built with
+    /// `SchemeObj::rational`, which keeps them in lowest terms with a
+    /// denominator greater than 1.
+    Rational {
+        num: isize,
+        den: isize,
+    },
+    /// A sequence of raw bytes, stored inline (after its length).
+    Bytevector(Vec<u8>),
+    /// A built-in procedure, given by its index in
+    /// `prims::PRIMITIVES`.
+    Primitive(usize),
+    /// A fixed-length sequence of objects, each held in a box (after the
+    /// vector's length).
+    Vector(Vec<SchemeObj>),
+    /// A character, stored as its Unicode scalar value.
+    Char(char),
+}
+
+impl SchemeObj {
+    /// Returns the rational `num/den`, reduced to lowest terms and with a
+    /// positive denominator. If the denominator reduces to 1, the result
+    /// is a plain `Number`. Returns `None` if the reduced fraction doesn't
+    /// fit in an `isize` numerator and denominator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `den` is 0.
+    pub fn rational(num: isize, den: isize) -> Option<SchemeObj> {
+        if den == 0 {
+            panic!("Rational with zero denominator: {}/0", num);
+        }
+
+        let negative = (num < 0) != (den < 0);
+        let divisor = gcd(num.unsigned_abs(), den.unsigned_abs());
+        let num_abs = (num.unsigned_abs() / divisor) as i128;
+        let num = isize::try_from(if negative { -num_abs } else { num_abs }).ok()?;
+        let den = isize::try_from(den.unsigned_abs() / divisor).ok()?;
+
+        Some(if den == 1 {
+            SchemeObj::Number(num)
+        } else {
+            SchemeObj::Rational { num, den }
+        })
+    }
+}
+
+/// Returns the greatest common divisor of `a` and `b`.
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    a
 }
 
 impl MemRead for SchemeObj {
@@ -25,7 +89,7 @@ impl MemRead for SchemeObj {
         match tag {
             Tag::Nil => Nil,
             Tag::Bool => {
-                let value = if mem.read(addr + 1) == 0 { false } else { true };
+                let value = mem.read(addr + 1) != 0;
                 Bool(value)
             }
             Tag::Number => Number(usize::read(mem, addr + 1) as isize),
@@ -36,7 +100,36 @@ impl MemRead for SchemeObj {
 
                 Pair { car, cdr }
             }
+            Tag::Eof => Eof,
+            Tag::Unspecified => Unspecified,
+            Tag::Rational => {
+                let num = usize::read(mem, addr + 1) as isize;
+                let den = usize::read(mem, addr + 1 + size_of::<usize>()) as isize;
+
+                Rational { num, den }
+            }
+            Tag::Bytevector => {
+                let len = usize::read(mem, addr + 1);
+                Bytevector(mem.read_bytes(addr + 1 + size_of::<usize>(), len))
+            }
+            Tag::Primitive => Primitive(usize::read(mem, addr + 1)),
+            Tag::Vector => {
+                let len = usize::read(mem, addr + 1);
+                let elems = (0..len)
+                    .map(|i| *Box::read(mem, vector_slot(mem, addr, i)))
+                    .collect();
+
+                Vector(elems)
+            }
+            Tag::Char => {
+                let value = usize::read(mem, addr + 1) as u32;
+                let c = char::from_u32(value)
+                    .unwrap_or_else(|| panic!("Invalid Char {:#x} at {}", value, addr));
+
+                Char(c)
+            }
             Tag::Box => panic!("Attempted to read Box at {}", addr),
+            Tag::WeakBox => panic!("Attempted to read WeakBox at {}", addr),
         }
     }
 }
@@ -56,7 +149,7 @@ impl MemWrite for SchemeObj {
             Number(n) => {
                 mem.write(addr, u8::from(Tag::Number));
 
-                let value = usize::from(*n as usize);
+                let value = *n as usize;
                 value.write(mem, addr + 1);
             }
             Symbol(i) => {
@@ -65,10 +158,68 @@ impl MemWrite for SchemeObj {
                 (*i).write(mem, addr + 1);
             }
             Pair { car, cdr } => {
+                // Allocating the car and cdr may trigger a collection, so
+                // the pair is rooted while they're written: first as a
+                // placeholder with no children, and then as a pair of
+                // `Nil`s whose slots are filled in as each child is
+                // allocated.
+                mem.write(addr, u8::from(Tag::Nil));
+                mem.push_temp_root(addr);
+
+                let nil = mem.nil();
                 mem.write(addr, u8::from(Tag::Pair));
+                mem.write(addr + 1, u8::from(Tag::Box));
+                Pointer(nil).write(mem, addr + 2);
+                mem.write(addr + 1 + BOX_SIZE, u8::from(Tag::Box));
+                Pointer(nil).write(mem, addr + 2 + BOX_SIZE);
 
-                car.write(mem, addr + 1);
-                cdr.write(mem, addr + 1 + size_of::<usize>());
+                let car_addr = alloc_boxed(mem, car);
+                set_car(mem, addr, car_addr);
+                let cdr_addr = alloc_boxed(mem, cdr);
+                set_cdr(mem, addr, cdr_addr);
+
+                mem.pop_temp_root();
+            }
+            Eof => mem.write(addr, u8::from(Tag::Eof)),
+            Unspecified => mem.write(addr, u8::from(Tag::Unspecified)),
+            Rational { num, den } => {
+                mem.write(addr, u8::from(Tag::Rational));
+
+                (*num as usize).write(mem, addr + 1);
+                (*den as usize).write(mem, addr + 1 + size_of::<usize>());
+            }
+            Bytevector(bytes) => {
+                mem.write(addr, u8::from(Tag::Bytevector));
+
+                bytes.len().write(mem, addr + 1);
+                mem.write_bytes(addr + 1 + size_of::<usize>(), bytes);
+            }
+            Primitive(i) => {
+                mem.write(addr, u8::from(Tag::Primitive));
+
+                (*i).write(mem, addr + 1);
+            }
+            Vector(elems) => {
+                // As with a pair, the vector is rooted (with each of its
+                // slots pointing at `Nil`) while its elements are
+                // allocated.
+                mem.write(addr, u8::from(Tag::Nil));
+                mem.push_temp_root(addr);
+
+                let nil = mem.nil();
+                init_vector(mem, addr, elems.len(), nil);
+
+                for (i, elem) in elems.iter().enumerate() {
+                    let elem_addr = alloc_boxed(mem, elem);
+                    vector_set(mem, addr, i, elem_addr);
+                }
+
+                mem.pop_temp_root();
+            }
+            Char(c) => {
+                mem.write(addr, u8::from(Tag::Char));
+
+                (*c as usize).write(mem, addr + 1);
             }
         }
     }
@@ -79,30 +230,485 @@ impl MemWrite for SchemeObj {
         let prim_size = 1 + size_of::<usize>();
 
         match self {
-            Nil | Bool(_) | Number(_) | Symbol(_) => prim_size,
-            Pair { .. } => 1 + 2 * prim_size,
+            Nil | Bool(_) | Number(_) | Symbol(_) | Primitive(_) | Char(_) => prim_size,
+            Pair { .. } => 1 + 2 * BOX_SIZE,
+            Eof | Unspecified => 1,
+            Rational { .. } => 1 + 2 * size_of::<usize>(),
+            Bytevector(bytes) => prim_size + bytes.len(),
+            Vector(elems) => prim_size + elems.len() * BOX_SIZE,
         }
     }
 }
 
 impl MemRead for Box<SchemeObj> {
     fn read<M: Mem>(mem: &M, addr: usize) -> Box<SchemeObj> {
-        Box::new(SchemeObj::read(mem, addr + 1))
+        Box::new(SchemeObj::read(mem, unbox(mem, addr)))
     }
 }
 
 impl MemWrite for Box<SchemeObj> {
     fn write<M: Mem>(&self, mem: &mut M, addr: usize) {
+        let obj_addr = alloc_boxed(mem, self);
         mem.write(addr, u8::from(Tag::Box));
-        let obj_addr = mem.alloc(&**self);
-        obj_addr.write(mem, addr + 1);
+        Pointer(obj_addr).write(mem, addr + 1);
     }
 
     fn size(&self) -> usize {
-        1 + size_of::<usize>()
+        BOX_SIZE
+    }
+}
+
+/// Allocates `obj` as the target of a box, returning its address. `Nil`
+/// isn't allocated afresh: the shared instance is used instead.
+fn alloc_boxed<M: Mem>(mem: &mut M, obj: &SchemeObj) -> usize {
+    match obj {
+        SchemeObj::Nil => mem.nil(),
+        _ => mem.alloc_obj(obj),
+    }
+}
+
+/// Returns the address stored in the box at `addr`. Weak boxes are read
+/// just like ordinary boxes.
+///
+/// # Panics
+///
+/// Panics if there is no box at `addr`, or if it is a weak box whose
+/// target has been collected.
+pub fn unbox<M: Mem>(mem: &M, addr: usize) -> usize {
+    match Tag::from(mem.read(addr)) {
+        Tag::Box => Pointer::read(mem, addr + 1).0,
+        Tag::WeakBox => {
+            weak_deref(mem, addr).unwrap_or_else(|| panic!("Broken WeakBox at {}", addr))
+        }
+        _ => panic!("Expected Box at {}", addr),
+    }
+}
+
+/// Allocates a new weak box pointing at the (already allocated) object at
+/// `target_addr`, and returns its address. Unlike an ordinary box, a
+/// weak box doesn't keep its target alive: the collector doesn't follow
+/// it while marking, and clears it if its target is swept.
+pub fn weak_box<M: Mem>(mem: &mut M, target_addr: usize) -> usize {
+    let addr = mem.alloc(&Box::new(SchemeObj::Nil));
+
+    mem.write(addr, u8::from(Tag::WeakBox));
+    Pointer(target_addr).write(mem, addr + 1);
+
+    addr
+}
+
+/// Returns the address of the object referred to by the weak box at
+/// `addr`, or `None` if that object has since been collected.
+///
+/// # Notes
+///
+/// A cleared weak box holds the address 0, which never refers to an
+/// object (as it's always occupied by the first block's header).
+///
+/// # Panics
+///
+/// Panics if there is no weak box at `addr`.
+pub fn weak_deref<M: Mem>(mem: &M, addr: usize) -> Option<usize> {
+    match Tag::from(mem.read(addr)) {
+        Tag::WeakBox => match Pointer::read(mem, addr + 1) {
+            Pointer(0) => None,
+            Pointer(target_addr) => Some(target_addr),
+        },
+        _ => panic!("Expected WeakBox at {}", addr),
+    }
+}
+
+/// Allocates a new pair whose car and cdr are boxes pointing at the
+/// (already allocated) objects at `car_addr` and `cdr_addr`, and returns
+/// its address.
+pub fn cons<M: Mem>(mem: &mut M, car_addr: usize, cdr_addr: usize) -> usize {
+    let addr = mem.alloc(&SchemeObj::Pair {
+        car: Box::new(SchemeObj::Nil),
+        cdr: Box::new(SchemeObj::Nil),
+    });
+
+    mem.write(addr, u8::from(Tag::Pair));
+    mem.write(addr + 1, u8::from(Tag::Box));
+    Pointer(car_addr).write(mem, addr + 2);
+    mem.write(addr + 1 + BOX_SIZE, u8::from(Tag::Box));
+    Pointer(cdr_addr).write(mem, addr + 2 + BOX_SIZE);
+
+    addr
+}
+
+/// Returns the address of the car of the pair at `addr`.
+///
+/// # Panics
+///
+/// Panics if there is no pair at `addr`.
+pub fn car<M: Mem>(mem: &M, addr: usize) -> usize {
+    match Tag::from(mem.read(addr)) {
+        Tag::Pair => unbox(mem, addr + 1),
+        _ => panic!("Expected Pair at {}", addr),
+    }
+}
+
+/// Returns the address of the cdr of the pair at `addr`.
+///
+/// # Panics
+///
+/// Panics if there is no pair at `addr`.
+pub fn cdr<M: Mem>(mem: &M, addr: usize) -> usize {
+    match Tag::from(mem.read(addr)) {
+        Tag::Pair => unbox(mem, addr + 1 + BOX_SIZE),
+        _ => panic!("Expected Pair at {}", addr),
+    }
+}
+
+/// Points the car of the pair at `addr` at the object at `car_addr`.
+///
+/// # Panics
+///
+/// Panics if there is no pair at `addr`.
+pub fn set_car<M: Mem>(mem: &mut M, addr: usize, car_addr: usize) {
+    match Tag::from(mem.read(addr)) {
+        Tag::Pair => {
+            mem.write_barrier(addr, car_addr);
+            Pointer(car_addr).write(mem, addr + 2);
+        }
+        _ => panic!("Expected Pair at {}", addr),
+    }
+}
+
+/// Points the cdr of the pair at `addr` at the object at `cdr_addr`.
+///
+/// # Panics
+///
+/// Panics if there is no pair at `addr`.
+pub fn set_cdr<M: Mem>(mem: &mut M, addr: usize, cdr_addr: usize) {
+    match Tag::from(mem.read(addr)) {
+        Tag::Pair => {
+            mem.write_barrier(addr, cdr_addr);
+            Pointer(cdr_addr).write(mem, addr + 2 + BOX_SIZE);
+        }
+        _ => panic!("Expected Pair at {}", addr),
+    }
+}
+
+/// Allocates a new vector of `len` slots, each pointing at the (already
+/// allocated) object at `fill_addr`, and returns its address. The fill
+/// object is kept as a temporary root while the vector is allocated.
+pub fn make_vector<M: Mem>(mem: &mut M, len: usize, fill_addr: usize) -> usize {
+    mem.push_temp_root(fill_addr);
+    let addr = mem.alloc(&SchemeObj::Vector(vec![SchemeObj::Nil; len]));
+    mem.pop_temp_root();
+
+    init_vector(mem, addr, len, fill_addr);
+
+    addr
+}
+
+/// Writes the tag and length of a vector of `len` slots to `addr`,
+/// pointing each slot at `fill_addr`.
+fn init_vector<M: Mem>(mem: &mut M, addr: usize, len: usize, fill_addr: usize) {
+    mem.write(addr, u8::from(Tag::Vector));
+    len.write(mem, addr + 1);
+
+    for i in 0..len {
+        let slot_addr = addr + 1 + size_of::<usize>() + i * BOX_SIZE;
+        mem.write(slot_addr, u8::from(Tag::Box));
+        Pointer(fill_addr).write(mem, slot_addr + 1);
+    }
+}
+
+/// Returns the number of slots in the vector at `addr`.
+///
+/// # Panics
+///
+/// Panics if there is no vector at `addr`.
+pub fn vector_length<M: Mem>(mem: &M, addr: usize) -> usize {
+    match Tag::from(mem.read(addr)) {
+        Tag::Vector => usize::read(mem, addr + 1),
+        _ => panic!("Expected Vector at {}", addr),
+    }
+}
+
+/// Returns the address of the `i`th element of the vector at `addr`.
+///
+/// # Panics
+///
+/// Panics if there is no vector at `addr`, or if `i` is out of bounds.
+pub fn vector_ref<M: Mem>(mem: &M, addr: usize, i: usize) -> usize {
+    unbox(mem, vector_slot(mem, addr, i))
+}
+
+/// Points the `i`th slot of the vector at `addr` at the object at
+/// `elem_addr`.
+///
+/// # Panics
+///
+/// Panics if there is no vector at `addr`, or if `i` is out of bounds.
+pub fn vector_set<M: Mem>(mem: &mut M, addr: usize, i: usize, elem_addr: usize) {
+    let slot_addr = vector_slot(mem, addr, i);
+
+    mem.write_barrier(addr, elem_addr);
+    Pointer(elem_addr).write(mem, slot_addr + 1);
+}
+
+/// Returns the address of the box holding the `i`th element of the
+/// vector at `addr`.
+fn vector_slot<M: Mem>(mem: &M, addr: usize, i: usize) -> usize {
+    let len = vector_length(mem, addr);
+    if i >= len {
+        panic!("Index {} out of bounds for Vector of length {}", i, len);
+    }
+
+    addr + 1 + size_of::<usize>() + i * BOX_SIZE
+}
+
+/// Returns the `i`th byte of the bytevector at `addr`.
+///
+/// # Panics
+///
+/// Panics if there is no bytevector at `addr`, or if `i` is out of
+/// bounds.
+pub fn bytevector_u8_ref<M: Mem>(mem: &M, addr: usize, i: usize) -> u8 {
+    mem.read(bytevector_index(mem, addr, i))
+}
+
+/// Sets the `i`th byte of the bytevector at `addr` to `byte`.
+///
+/// # Panics
+///
+/// Panics if there is no bytevector at `addr`, or if `i` is out of
+/// bounds.
+pub fn bytevector_u8_set<M: Mem>(mem: &mut M, addr: usize, i: usize, byte: u8) {
+    let byte_addr = bytevector_index(mem, addr, i);
+    mem.write(byte_addr, byte);
+}
+
+/// Returns the address of the `i`th byte of the bytevector at `addr`.
+fn bytevector_index<M: Mem>(mem: &M, addr: usize, i: usize) -> usize {
+    match Tag::from(mem.read(addr)) {
+        Tag::Bytevector => {
+            let len = usize::read(mem, addr + 1);
+            if i >= len {
+                panic!("Index {} out of bounds for Bytevector of length {}", i, len);
+            }
+
+            addr + 1 + size_of::<usize>() + i
+        }
+        _ => panic!("Expected Bytevector at {}", addr),
+    }
+}
+
+/// Allocates a structurally identical copy of the object at `src_addr`
+/// (and everything reachable from it), returning the address of the
+/// copy.
+///
+/// # Notes
+///
+/// We maintain a map from each object we've copied to its copy, so that
+/// an object reachable along several paths is only copied once. This
+/// preserves shared structure in the copy, and ensures that copying a
+/// cyclic structure terminates. `Nil` isn't copied, as it is shared.
+///
+/// Each allocation may trigger a collection, and the copies are only
+/// held by the forwarding map, so the source and each copy are kept as
+/// temporary roots until the copy is complete.
+pub fn deep_copy<M: Mem>(mem: &mut M, src_addr: usize) -> usize {
+    let mut forwarding = HashMap::new();
+    let mut pairs = vec![];
+    let mut vectors = vec![];
+    let mut pending = vec![src_addr];
+
+    mem.push_temp_root(src_addr);
+
+    while let Some(addr) = pending.pop() {
+        if forwarding.contains_key(&addr) {
+            continue;
+        }
+
+        let copy = match Tag::from(mem.read(addr)) {
+            Tag::Pair => {
+                let (car_addr, cdr_addr) = (car(mem, addr), cdr(mem, addr));
+                pending.push(cdr_addr);
+                pending.push(car_addr);
+                pairs.push(addr);
+
+                // For now, the copy points at the original's children;
+                // we redirect it once they've been copied.
+                cons(mem, car_addr, cdr_addr)
+            }
+            Tag::Vector => {
+                let len = vector_length(mem, addr);
+                pending.extend((0..len).rev().map(|i| vector_ref(mem, addr, i)));
+                vectors.push(addr);
+
+                let nil = mem.nil();
+                make_vector(mem, len, nil)
+            }
+            Tag::Nil => mem.nil(),
+            _ => {
+                let obj = SchemeObj::read(mem, addr);
+                mem.alloc_obj(&obj)
+            }
+        };
+
+        mem.push_temp_root(copy);
+        forwarding.insert(addr, copy);
+    }
+
+    for addr in pairs {
+        let copy = forwarding[&addr];
+        let car_copy = forwarding[&car(mem, addr)];
+        let cdr_copy = forwarding[&cdr(mem, addr)];
+
+        set_car(mem, copy, car_copy);
+        set_cdr(mem, copy, cdr_copy);
+    }
+
+    for addr in vectors {
+        let copy = forwarding[&addr];
+
+        for i in 0..vector_length(mem, addr) {
+            let elem_copy = forwarding[&vector_ref(mem, addr, i)];
+            vector_set(mem, copy, i, elem_copy);
+        }
+    }
+
+    for _ in 0..=forwarding.len() {
+        mem.pop_temp_root();
+    }
+
+    forwarding[&src_addr]
+}
+
+/// Determines if the objects stored at `a_addr` and `b_addr` are the
+/// same object, in the sense of Scheme's `eq?`. As booleans, symbols,
+/// characters, primitives, and the singletons (like `Nil`) aren't
+/// necessarily shared on our heap, they're compared by value; every other object
+/// (including numbers) is compared by address.
+pub fn scheme_eq<M: Mem>(mem: &M, a_addr: usize, b_addr: usize) -> bool {
+    identical(mem, a_addr, b_addr, false)
+}
+
+/// Determines if the objects stored at `a_addr` and `b_addr` are
+/// equivalent, in the sense of Scheme's `eqv?`. This is like
+/// `scheme_eq`, except that numbers (including rationals) are compared
+/// by value.
+pub fn scheme_eqv<M: Mem>(mem: &M, a_addr: usize, b_addr: usize) -> bool {
+    identical(mem, a_addr, b_addr, true)
+}
+
+/// Implements `scheme_eq` and `scheme_eqv`, comparing numbers by value
+/// only if `numbers_by_value` is set.
+fn identical<M: Mem>(mem: &M, a_addr: usize, b_addr: usize, numbers_by_value: bool) -> bool {
+    if a_addr == b_addr {
+        return true;
+    }
+
+    let tag = Tag::from(mem.read(a_addr));
+    if tag != Tag::from(mem.read(b_addr)) {
+        return false;
+    }
+
+    let word = size_of::<usize>();
+    match tag {
+        Tag::Nil | Tag::Eof | Tag::Unspecified => true,
+        Tag::Bool => mem.read(a_addr + 1) == mem.read(b_addr + 1),
+        Tag::Symbol | Tag::Primitive | Tag::Char => {
+            usize::read(mem, a_addr + 1) == usize::read(mem, b_addr + 1)
+        }
+        Tag::Number if numbers_by_value => {
+            usize::read(mem, a_addr + 1) == usize::read(mem, b_addr + 1)
+        }
+        Tag::Rational if numbers_by_value => {
+            mem.read_bytes(a_addr + 1, 2 * word) == mem.read_bytes(b_addr + 1, 2 * word)
+        }
+        _ => false,
     }
 }
 
+/// Determines if the objects stored at `a_addr` and `b_addr` are
+/// structurally equal, in the sense of Scheme's `equal?`: numbers,
+/// booleans, symbols, and characters are compared by value, pairs are equal if
+/// their cars and cdrs are (recursively) equal, and vectors are equal if
+/// they have the same length and equal elements.
+///
+/// # Notes
+///
+/// To avoid looping forever on cyclic structures, we remember each pair
+/// of pairs (or vectors) we've started comparing. If we encounter the
+/// same two again, we've come full circle without finding a difference,
+/// so we can consider them equal.
+pub fn scheme_equal<M: Mem>(mem: &M, a_addr: usize, b_addr: usize) -> bool {
+    let mut visited = HashSet::new();
+    let mut pending = vec![(a_addr, b_addr)];
+
+    while let Some((a, b)) = pending.pop() {
+        if a == b {
+            continue;
+        }
+
+        let tag = Tag::from(mem.read(a));
+        if tag != Tag::from(mem.read(b)) {
+            return false;
+        }
+
+        match tag {
+            Tag::Nil | Tag::Eof | Tag::Unspecified => (),
+            Tag::Bool => {
+                if mem.read(a + 1) != mem.read(b + 1) {
+                    return false;
+                }
+            }
+            Tag::Number | Tag::Symbol | Tag::Primitive | Tag::Char => {
+                if usize::read(mem, a + 1) != usize::read(mem, b + 1) {
+                    return false;
+                }
+            }
+            Tag::Bytevector => {
+                let len = usize::read(mem, a + 1);
+                if len != usize::read(mem, b + 1) {
+                    return false;
+                }
+
+                let word = size_of::<usize>();
+                if mem.read_bytes(a + 1 + word, len) != mem.read_bytes(b + 1 + word, len) {
+                    return false;
+                }
+            }
+            Tag::Rational => {
+                let word = size_of::<usize>();
+                if mem.read_bytes(a + 1, 2 * word) != mem.read_bytes(b + 1, 2 * word) {
+                    return false;
+                }
+            }
+            Tag::Pair => {
+                if !visited.insert((a, b)) {
+                    continue;
+                }
+
+                pending.push((unbox(mem, a + 1 + BOX_SIZE), unbox(mem, b + 1 + BOX_SIZE)));
+                pending.push((unbox(mem, a + 1), unbox(mem, b + 1)));
+            }
+            Tag::Vector => {
+                let len = vector_length(mem, a);
+                if len != vector_length(mem, b) {
+                    return false;
+                }
+                if !visited.insert((a, b)) {
+                    continue;
+                }
+
+                for i in (0..len).rev() {
+                    pending.push((vector_ref(mem, a, i), vector_ref(mem, b, i)));
+                }
+            }
+            Tag::Box => panic!("Attempted to compare Box at {}", a),
+            Tag::WeakBox => panic!("Attempted to compare WeakBox at {}", a),
+        }
+    }
+
+    true
+}
+
 /// Used to indicate the type of object represented by the following
 /// bytes in memory.
 #[derive(PartialEq)]
@@ -113,6 +719,14 @@ pub enum Tag {
     Number,
     Symbol,
     Pair,
+    Eof,
+    Unspecified,
+    WeakBox,
+    Rational,
+    Bytevector,
+    Primitive,
+    Vector,
+    Char,
 }
 
 impl From<u8> for Tag {
@@ -126,6 +740,14 @@ impl From<u8> for Tag {
             3 => Number,
             4 => Symbol,
             5 => Pair,
+            6 => Eof,
+            7 => Unspecified,
+            8 => WeakBox,
+            9 => Rational,
+            10 => Bytevector,
+            11 => Primitive,
+            12 => Vector,
+            13 => Char,
             _ => panic!("No tag associated with byte: {}", byte),
         }
     }
@@ -142,6 +764,318 @@ impl From<Tag> for u8 {
             Number => 3,
             Symbol => 4,
             Pair => 5,
+            Eof => 6,
+            Unspecified => 7,
+            WeakBox => 8,
+            Rational => 9,
+            Bytevector => 10,
+            Primitive => 11,
+            Vector => 12,
+            Char => 13,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::Heap;
+
+    fn num(n: isize) -> Box<SchemeObj> {
+        Box::new(SchemeObj::Number(n))
+    }
+
+    fn cons(car: Box<SchemeObj>, cdr: Box<SchemeObj>) -> Box<SchemeObj> {
+        Box::new(SchemeObj::Pair { car, cdr })
+    }
+
+    fn nil() -> Box<SchemeObj> {
+        Box::new(SchemeObj::Nil)
+    }
+
+    #[test]
+    fn write_read_singletons() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        assert_eq!(SchemeObj::Eof.size(), 1);
+        assert_eq!(SchemeObj::Unspecified.size(), 1);
+
+        let eof = mem.alloc_obj(&SchemeObj::Eof);
+        let unspecified = mem.alloc_obj(&SchemeObj::Unspecified);
+
+        assert!(matches!(SchemeObj::read(&mem, eof), SchemeObj::Eof));
+        assert!(matches!(
+            SchemeObj::read(&mem, unspecified),
+            SchemeObj::Unspecified
+        ));
+        assert!(!scheme_equal(&mem, eof, unspecified));
+    }
+
+    #[test]
+    fn rational_lowest_terms() {
+        assert_eq!(
+            SchemeObj::rational(2, 4),
+            Some(SchemeObj::Rational { num: 1, den: 2 })
+        );
+        assert_eq!(
+            SchemeObj::rational(3, -6),
+            Some(SchemeObj::Rational { num: -1, den: 2 })
+        );
+        assert_eq!(SchemeObj::rational(0, 5), Some(SchemeObj::Number(0)));
+        assert_eq!(SchemeObj::rational(4, 2), Some(SchemeObj::Number(2)));
+    }
+
+    #[test]
+    fn rational_extremes() {
+        assert_eq!(
+            SchemeObj::rational(isize::MIN, 1),
+            Some(SchemeObj::Number(isize::MIN))
+        );
+        assert_eq!(
+            SchemeObj::rational(isize::MIN, isize::MIN),
+            Some(SchemeObj::Number(1))
+        );
+        assert_eq!(
+            SchemeObj::rational(2, isize::MIN),
+            Some(SchemeObj::Rational {
+                num: -1,
+                den: 1 << (isize::BITS - 2)
+            })
+        );
+        assert_eq!(SchemeObj::rational(1, isize::MIN), None);
+        assert_eq!(SchemeObj::rational(isize::MIN, -1), None);
+    }
+
+    #[test]
+    fn write_read_rational() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        let half = SchemeObj::rational(-2, 4).unwrap();
+        let a = mem.alloc_obj(&half);
+        let b = mem.alloc_obj(&SchemeObj::rational(1, -2).unwrap());
+
+        assert_eq!(SchemeObj::read(&mem, a), half);
+        assert!(scheme_equal(&mem, a, b));
+    }
+
+    #[test]
+    fn write_read_bytevector() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        let bytes = SchemeObj::Bytevector(vec![1, 2, 3, 255]);
+        let addr = mem.alloc_obj(&bytes);
+
+        assert_eq!(SchemeObj::read(&mem, addr), bytes);
+        assert_eq!(bytevector_u8_ref(&mem, addr, 3), 255);
+
+        bytevector_u8_set(&mut mem, addr, 0, 42);
+        assert_eq!(
+            SchemeObj::read(&mem, addr),
+            SchemeObj::Bytevector(vec![42, 2, 3, 255])
+        );
+
+        let copy = mem.alloc_obj(&SchemeObj::Bytevector(vec![42, 2, 3, 255]));
+        assert!(scheme_equal(&mem, addr, copy));
+        assert!(crate::heap::children(&mem, addr).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn bytevector_out_of_bounds() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        let addr = mem.alloc_obj(&SchemeObj::Bytevector(vec![1, 2, 3]));
+        bytevector_u8_set(&mut mem, addr, 3, 0);
+    }
+
+    #[test]
+    fn write_read_vector() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let vector = SchemeObj::Vector(vec![*num(1), *cons(num(2), nil()), SchemeObj::Nil]);
+        let addr = mem.alloc_obj(&vector);
+
+        assert_eq!(SchemeObj::read(&mem, addr), vector);
+        assert_eq!(vector_length(&mem, addr), 3);
+        assert_eq!(vector_ref(&mem, addr, 2), mem.nil());
+        assert_eq!(crate::heap::children(&mem, addr).len(), 3);
+
+        let copy = mem.alloc_obj(&vector);
+        assert!(scheme_equal(&mem, addr, copy));
+
+        let five = mem.alloc_obj(&SchemeObj::Number(5));
+        vector_set(&mut mem, copy, 0, five);
+        assert!(!scheme_equal(&mem, addr, copy));
+    }
+
+    #[test]
+    fn write_vector_under_pressure() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+        mem.set_gc_threshold(1.0);
+
+        let vector = SchemeObj::Vector(vec![*num(1), *cons(num(2), nil()), *num(3)]);
+        let addr = mem.alloc_obj(&vector);
+
+        assert_eq!(SchemeObj::read(&mem, addr), vector);
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn vector_out_of_bounds() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        let addr = mem.alloc_obj(&SchemeObj::Vector(vec![*num(1)]));
+        vector_ref(&mem, addr, 1);
+    }
+
+    #[test]
+    fn write_read_char() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let a = mem.alloc_obj(&SchemeObj::Char('λ'));
+        let b = mem.alloc_obj(&SchemeObj::Char('λ'));
+        let c = mem.alloc_obj(&SchemeObj::Char('A'));
+
+        assert_eq!(SchemeObj::read(&mem, a), SchemeObj::Char('λ'));
+        assert!(scheme_eqv(&mem, a, b));
+        assert!(scheme_equal(&mem, a, b));
+        assert!(!scheme_eqv(&mem, a, c));
+        assert_eq!(
+            mem.intern_constant(&SchemeObj::Char('A')),
+            mem.intern_constant(&SchemeObj::Char('A'))
+        );
+    }
+
+    #[test]
+    fn clone_debug() {
+        let pair = cons(num(1), cons(num(2), nil()));
+        let copy = pair.clone();
+
+        assert_eq!(format!("{:?}", copy), format!("{:?}", pair));
+        assert_eq!(
+            format!("{:?}", copy),
+            "Pair { car: Number(1), cdr: Pair { car: Number(2), cdr: Nil } }"
+        );
+    }
+
+    #[test]
+    fn partial_eq_nested_pairs() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        // (1 (2))
+        let list = || cons(num(1), cons(cons(num(2), nil()), nil()));
+        assert_eq!(*list(), *list());
+        assert_ne!(*list(), *cons(num(1), nil()));
+
+        let addr = mem.alloc_obj(&*list());
+        assert_eq!(SchemeObj::read(&mem, addr), *list());
+
+        let five = mem.alloc_obj(&SchemeObj::Number(5));
+        assert_eq!(SchemeObj::read(&mem, five), SchemeObj::Number(5));
+    }
+
+    #[test]
+    fn equal_nested_lists() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // (1 (2 3))
+        let list = || cons(num(1), cons(cons(num(2), cons(num(3), nil())), nil()));
+
+        let a = mem.alloc_obj(&*list());
+        let b = mem.alloc_obj(&*list());
+
+        assert_ne!(a, b);
+        assert!(scheme_equal(&mem, a, b));
+    }
+
+    #[test]
+    fn unequal_lists() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let a = mem.alloc_obj(&*cons(num(1), cons(num(2), nil())));
+        let b = mem.alloc_obj(&*cons(num(1), cons(num(3), nil())));
+
+        assert!(!scheme_equal(&mem, a, b));
+    }
+
+    #[test]
+    fn equal_cyclic_lists() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let a = mem.alloc_obj(&*cons(num(1), nil()));
+        let b = mem.alloc_obj(&*cons(num(1), nil()));
+
+        // Point each pair's cdr back at itself.
+        let cdr_slot = 2 + BOX_SIZE;
+        Pointer(a).write(&mut mem, a + cdr_slot);
+        Pointer(b).write(&mut mem, b + cdr_slot);
+
+        assert!(scheme_equal(&mem, a, b));
+    }
+
+    #[test]
+    fn deep_copy_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let src = mem.alloc_obj(&*cons(num(1), cons(num(2), nil())));
+        let copy = deep_copy(&mut mem, src);
+
+        assert!(scheme_equal(&mem, src, copy));
+        assert_ne!(copy, src);
+        assert_ne!(car(&mem, copy), car(&mem, src));
+        assert_ne!(cdr(&mem, copy), cdr(&mem, src));
+        assert_eq!(cdr(&mem, cdr(&mem, copy)), mem.nil());
+    }
+
+    #[test]
+    fn deep_copy_survives_collection() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let src = mem.alloc_obj(&*cons(num(1), cons(num(2), cons(num(3), nil()))));
+
+        // Every allocation now collects, so the source (which isn't
+        // otherwise rooted) and the copies made so far must be rooted.
+        mem.set_gc_threshold(1.0);
+        let copy = deep_copy(&mut mem, src);
+        mem.set_gc_threshold(0.0);
+
+        assert!(scheme_equal(&mem, src, copy));
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn deep_copy_shared_and_cyclic() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // A pair whose car and cdr are both the same pair, whose cdr in
+        // turn points back at itself.
+        let inner = mem.alloc_obj(&*cons(num(1), nil()));
+        set_cdr(&mut mem, inner, inner);
+        let src = super::cons(&mut mem, inner, inner);
+
+        let copy = deep_copy(&mut mem, src);
+        let inner_copy = car(&mem, copy);
+
+        assert_ne!(inner_copy, inner);
+        assert_eq!(cdr(&mem, copy), inner_copy);
+        assert_eq!(cdr(&mem, inner_copy), inner_copy);
+        assert!(scheme_equal(&mem, src, copy));
+    }
+
+    #[test]
+    fn deep_copy_cyclic_vector() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // A vector whose second slot holds the vector itself.
+        let src = mem.alloc_obj(&SchemeObj::Vector(vec![*num(1), SchemeObj::Nil]));
+        vector_set(&mut mem, src, 1, src);
+
+        let copy = deep_copy(&mut mem, src);
+
+        assert_ne!(copy, src);
+        assert_ne!(vector_ref(&mem, copy, 0), vector_ref(&mem, src, 0));
+        assert_eq!(vector_ref(&mem, copy, 1), copy);
+        assert!(scheme_equal(&mem, src, copy));
+    }
+}
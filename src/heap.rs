@@ -1,232 +1,2908 @@
-use crate::data::{SchemeObj, Tag};
-use crate::memory::{Header, Mem, MemRead, MemWrite};
+use crate::data::{SchemeObj, Tag, BOX_SIZE};
+use crate::memory::{Header, Mem, MemRead, MemWrite, Pointer, MAGIC, POINTER_WIDTH};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+/// The address at which the large-object region begins. Large objects
+/// live in their own backing storage, but share the heap's address
+/// space, so this is placed halfway through the addresses a pointer can
+/// hold (see `POINTER_WIDTH`), and the main region can't grow past it.
+pub const LARGE_BASE: usize = 1 << (8 * POINTER_WIDTH - 1);
 
 pub struct Heap {
     space: Vec<u8>,
     get_roots: Box<dyn Fn() -> Vec<usize>>,
+    gc_threshold: f64,
+    gc_trigger: Option<usize>,
+    gc_stats: GcStats,
+    alloc_sizes: BTreeMap<usize, usize>,
+    gray: Option<Vec<usize>>,
+    max_size: usize,
+    constants: HashMap<(u8, usize, usize), usize>,
+    live_bytes: usize,
+    free_bytes: usize,
+    large: Vec<u8>,
+    large_blocks: Vec<usize>,
+    large_threshold: Option<usize>,
+    finalizers: HashMap<usize, Box<dyn FnOnce()>>,
+    strategy: AllocStrategy,
+    temp_roots: Vec<usize>,
+    generations: Option<Generations>,
+}
+
+/// The bookkeeping for a generational heap (see `Heap::new_generational`).
+///
+/// # Notes
+///
+/// Generations aren't separate regions: every block lives in the same
+/// chain, and a block is young if it's been allocated since the last
+/// collection. Young blocks that survive a collection are promoted (to
+/// the old generation) in place, so that addresses held outside of the
+/// heap remain valid.
+struct Generations {
+    young: HashSet<usize>,
+    young_bytes: usize,
+    young_size: usize,
+    remembered: HashSet<usize>,
+}
+
+/// Determines which free block `Heap` chooses to satisfy an allocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocStrategy {
+    /// Use the first free block (in chain order) that's large enough.
+    FirstFit,
+    /// Use the smallest free block that's large enough, so that larger
+    /// blocks aren't split unnecessarily. This requires scanning the
+    /// entire chain.
+    BestFit,
+}
+
+/// Reports whether a call to `Heap::collect_incremental` completed the
+/// collection cycle, or whether more steps are needed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollectProgress {
+    Done,
+    More,
+}
+
+/// Running statistics about the collector's activity, accumulated since
+/// the heap was created (or since the last call to
+/// `Heap::reset_gc_stats`).
+///
+/// # Notes
+///
+/// `bytes_reclaimed` is measured as the growth in free bytes across each
+/// sweep, so it includes the headers of any blocks that were coalesced
+/// away.
+///
+/// `collections` includes the `minor_collections` of a generational
+/// heap, and `objects_scanned` counts the objects marked (and so scanned
+/// for children) across all collections.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub minor_collections: usize,
+    pub bytes_reclaimed: usize,
+    pub last_pause: Duration,
+    pub objects_scanned: usize,
+}
+
+/// Describes a violated heap invariant, as found by `Heap::verify`.
+#[derive(Debug, PartialEq)]
+pub enum HeapError {
+    /// There's no valid header (its magic byte is missing) at the
+    /// indicated address.
+    CorruptHeader(usize),
+    /// The block at `addr` extends past the end of the heap.
+    Overrun { addr: usize, end: usize },
+    /// The block at `addr` doesn't point at the block immediately
+    /// following it (or, for the last block, doesn't end the chain).
+    BadNext {
+        addr: usize,
+        next: usize,
+        expected: usize,
+    },
+    /// The heap's running count of live bytes doesn't match its blocks.
+    LiveBytes { recorded: usize, actual: usize },
+    /// The heap's running count of free bytes doesn't match its blocks.
+    FreeBytes { recorded: usize, actual: usize },
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use HeapError::*;
+
+        match self {
+            CorruptHeader(addr) => write!(f, "corrupt header at {}", addr),
+            Overrun { addr, end } => {
+                write!(f, "block at {} overruns the heap (ends at {})", addr, end)
+            }
+            BadNext {
+                addr,
+                next,
+                expected,
+            } => write!(
+                f,
+                "block at {} has next {}, but should have {}",
+                addr, next, expected
+            ),
+            LiveBytes { recorded, actual } => write!(
+                f,
+                "recorded {} live bytes, but blocks hold {}",
+                recorded, actual
+            ),
+            FreeBytes { recorded, actual } => write!(
+                f,
+                "recorded {} free bytes, but blocks hold {}",
+                recorded, actual
+            ),
+        }
+    }
 }
 
 impl Mem for Heap {
     fn write(&mut self, addr: usize, datum: u8) {
-        self.space[addr] = datum;
+        let (region, offset) = self.region_mut(addr);
+        region[offset] = datum;
     }
 
     fn read(&self, addr: usize) -> u8 {
-        self.space[addr]
+        let (region, offset) = self.region(addr);
+        region[offset]
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Vec<u8> {
+        let (region, offset) = self.region(addr);
+        region[offset..offset + len].to_vec()
+    }
+
+    fn write_bytes(&mut self, addr: usize, data: &[u8]) {
+        let (region, offset) = self.region_mut(addr);
+        region[offset..offset + data.len()].copy_from_slice(data);
     }
 
     fn alloc<T: MemWrite>(&mut self, obj: &T) -> usize {
         self.alloc_bytes(obj.size(), true)
     }
+
+    /// Remembers any old object that's made to point at a young one, so
+    /// that a minor collection can treat its children as roots.
+    ///
+    /// While an incremental collection is in progress, the child is also
+    /// shaded gray. Otherwise, storing an unmarked object into one that's
+    /// already been scanned would hide it from the marker, and it would
+    /// be freed while still reachable.
+    fn write_barrier(&mut self, parent_addr: usize, child_addr: usize) {
+        let header_size = Header::new(0, 0, false).size();
+
+        if let Some(gray) = &mut self.gray {
+            gray.push(child_addr);
+        }
+
+        if let Some(gens) = &mut self.generations {
+            if !gens.young.contains(&(parent_addr - header_size))
+                && gens.young.contains(&(child_addr - header_size))
+            {
+                gens.remembered.insert(parent_addr);
+            }
+        }
+    }
+
+    /// Pushes `addr` onto a stack of temporary roots, which are treated
+    /// as roots (in addition to those returned by `get_roots`) until
+    /// they're popped.
+    ///
+    /// # Notes
+    ///
+    /// Temporary roots are remapped when `Heap::compact` moves their
+    /// objects, so callers should re-read them (via `pop_temp_root`)
+    /// rather than hold onto their original addresses.
+    fn push_temp_root(&mut self, addr: usize) {
+        self.temp_roots.push(addr);
+    }
+
+    /// Pops the most recently pushed temporary root, returning its
+    /// (possibly remapped) address.
+    fn pop_temp_root(&mut self) -> Option<usize> {
+        self.temp_roots.pop()
+    }
+
+    /// Returns the address of the heap's single shared `Nil`, drawn from
+    /// the constants pool (see `Heap::intern_constant`).
+    fn nil(&mut self) -> usize {
+        self.intern_constant(&SchemeObj::Nil)
+    }
 }
 
 // TODO Implement `Iterator` for `Heap`.
 impl Heap {
+    /// Creates a heap of `size` bytes, whose roots (at collection time)
+    /// are given by `get_roots`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` can't even hold a single block header, or if it
+    /// reaches past `LARGE_BASE`.
     pub fn new(size: usize, get_roots: Box<dyn Fn() -> Vec<usize>>) -> Heap {
+        let header_size = Header::new(0, 0, false).size();
+        if size < header_size {
+            panic!("heap size {} too small; minimum is {}", size, header_size);
+        }
+        check_max_size(size);
+
         let space = vec![0; size];
-        let mut mem = Heap { space, get_roots };
+        let mut mem = Heap {
+            space,
+            get_roots,
+            gc_threshold: 0.0,
+            gc_trigger: None,
+            gc_stats: GcStats::default(),
+            alloc_sizes: BTreeMap::new(),
+            gray: None,
+            max_size: size,
+            constants: HashMap::new(),
+            live_bytes: 0,
+            free_bytes: size - header_size,
+            large: vec![],
+            large_blocks: vec![],
+            large_threshold: None,
+            finalizers: HashMap::new(),
+            strategy: AllocStrategy::FirstFit,
+            temp_roots: vec![],
+            generations: None,
+        };
 
         // IMPORTANT We initialize this header's `size` to the entire
         // size of the memory we have. However, this isn't correct: we
         // need to subtract the size of the header itself. However, this
         // is easiest to do _after_ the header has already been created.
         let mut header = Header::new(0, size, false);
-        let header_size = header.size();
         header.size = size - header_size;
         header.write(&mut mem, 0);
 
         mem
     }
 
+    /// Reconstructs a heap over an existing image, such as one produced
+    /// by `Heap::to_bytes`. Unlike `Heap::new`, this doesn't initialize
+    /// the first header: `data` must already hold a valid block chain.
+    ///
     /// # Notes
     ///
-    /// Returns the address of the first byte _within_ the allocated
-    /// block, and *not* the address of the block header.
-    fn alloc_bytes(&mut self, n: usize, attempt_collect: bool) -> usize {
-        let mut header: Header;
-        let mut header_addr = 0;
+    /// The constants pool isn't part of the image, so constants interned
+    /// after restoring (including the shared `Nil`) are freshly
+    /// allocated rather than shared with those in the image.
+    pub fn from_bytes(data: Vec<u8>, get_roots: Box<dyn Fn() -> Vec<usize>>) -> Heap {
+        let max_size = data.len();
+        check_max_size(max_size);
 
-        loop {
-            header = Header::read(self, header_addr);
+        let mut mem = Heap {
+            space: data,
+            get_roots,
+            gc_threshold: 0.0,
+            gc_trigger: None,
+            gc_stats: GcStats::default(),
+            alloc_sizes: BTreeMap::new(),
+            gray: None,
+            max_size,
+            constants: HashMap::new(),
+            live_bytes: 0,
+            free_bytes: 0,
+            large: vec![],
+            large_blocks: vec![],
+            large_threshold: None,
+            finalizers: HashMap::new(),
+            strategy: AllocStrategy::FirstFit,
+            temp_roots: vec![],
+            generations: None,
+        };
 
-            if !header.allocd && header.size >= n {
-                self.alloc_block(&mut header, n);
-                header.write(self, header_addr);
-                return header_addr + header.size();
-            }
+        mem.live_bytes = mem
+            .block_addrs()
+            .into_iter()
+            .map(|addr| Header::read(&mem, addr))
+            .filter(|header| header.allocd)
+            .map(|header| header.size)
+            .sum();
+        mem.free_bytes = mem.count_free_bytes();
 
-            if header.next == 0 {
-                if attempt_collect {
-                    self.collect();
-                    return self.alloc_bytes(n, false);
-                }
+        mem
+    }
 
-                panic!("Unable to allocate: out of memory");
-            }
+    /// Returns a snapshot of the heap's entire contents, suitable for
+    /// restoring with `Heap::from_bytes`.
+    ///
+    /// # Notes
+    ///
+    /// Only the main region is included: objects in the large-object
+    /// region (see `Heap::set_large_object_threshold`) aren't part of the
+    /// image.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.space.clone()
+    }
 
-            header_addr = header.next;
+    /// Creates a heap of `initial` bytes that, rather than panicking when
+    /// an allocation can't be satisfied even after a collection, doubles
+    /// its capacity (up to `max` bytes) and tries again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` reaches past `LARGE_BASE`.
+    pub fn new_growable(
+        initial: usize,
+        max: usize,
+        get_roots: Box<dyn Fn() -> Vec<usize>>,
+    ) -> Heap {
+        check_max_size(max);
+
+        let mut mem = Heap::new(initial, get_roots);
+        mem.max_size = max;
+        mem
+    }
+
+    /// Creates a heap of `size` bytes that chooses free blocks according
+    /// to `strategy`. (`Heap::new` uses `AllocStrategy::FirstFit`.)
+    pub fn new_with_strategy(
+        size: usize,
+        strategy: AllocStrategy,
+        get_roots: Box<dyn Fn() -> Vec<usize>>,
+    ) -> Heap {
+        let mut mem = Heap::new(size, get_roots);
+        mem.strategy = strategy;
+        mem
+    }
+
+    /// Creates a generational heap of `young_size + old_size` bytes.
+    /// Once `young_size` bytes have been allocated since the last
+    /// collection, the next allocation first runs a minor collection
+    /// (see `Heap::collect_minor`), which only traces young objects.
+    pub fn new_generational(
+        young_size: usize,
+        old_size: usize,
+        get_roots: Box<dyn Fn() -> Vec<usize>>,
+    ) -> Heap {
+        let mut mem = Heap::new(young_size + old_size, get_roots);
+        mem.generations = Some(Generations {
+            young: HashSet::new(),
+            young_bytes: 0,
+            young_size,
+            remembered: HashSet::new(),
+        });
+        mem
+    }
+
+    /// Returns the address of a shared copy of `obj` from the heap's
+    /// constants pool, allocating it the first time a given constant is
+    /// interned. Interning equal constants always yields the same
+    /// address, and pooled constants are always treated as roots, so
+    /// they are never collected.
+    ///
+    /// # Panics
+    ///
+    /// Only atoms (`Nil`, booleans, numbers, rationals, symbols,
+    /// characters, primitives, `Eof`, and `Unspecified`) can be interned;
+    /// `intern_constant` panics if given a pair, a bytevector, or a
+    /// vector.
+    pub fn intern_constant(&mut self, obj: &SchemeObj) -> usize {
+        use SchemeObj::*;
+
+        let key = match obj {
+            Nil => (u8::from(Tag::Nil), 0, 0),
+            Bool(b) => (u8::from(Tag::Bool), *b as usize, 0),
+            Number(n) => (u8::from(Tag::Number), *n as usize, 0),
+            Symbol(i) => (u8::from(Tag::Symbol), *i, 0),
+            Primitive(i) => (u8::from(Tag::Primitive), *i, 0),
+            Char(c) => (u8::from(Tag::Char), *c as usize, 0),
+            Eof => (u8::from(Tag::Eof), 0, 0),
+            Unspecified => (u8::from(Tag::Unspecified), 0, 0),
+            Rational { num, den } => (u8::from(Tag::Rational), *num as usize, *den as usize),
+            Pair { .. } => panic!("Attempted to intern a Pair"),
+            Bytevector(_) => panic!("Attempted to intern a Bytevector"),
+            Vector(_) => panic!("Attempted to intern a Vector"),
+        };
+
+        match self.constants.get(&key) {
+            Some(&addr) => addr,
+            None => {
+                let addr = self.alloc_obj(obj);
+                self.constants.insert(key, addr);
+                addr
+            }
         }
     }
 
-    /// Marks the block headed by `header` as allocated, and -- if the
-    /// block is large enough -- splits it into two blocks where the
-    /// second is unallocated.
+    /// Sets the fraction of the heap's capacity below which free space
+    /// triggers a collection *before* an allocation is attempted. A
+    /// threshold of `0.0` (the default) disables proactive collection,
+    /// so that collection only happens when an allocation fails.
     ///
     /// # Notes
     ///
-    /// We still need to write our updated (original) header to memory,
-    /// via `header.write(..)`, in order to persist the changes we've
-    /// made to it. At the moment, we do this in the caller
-    /// (`self.alloc_bytes`), but it may make more sense to do it here.
-    fn alloc_block(&mut self, header: &mut Header, n: usize) {
-        header.allocd = true;
+    /// If a collection leaves free space below the threshold (because
+    /// the live data doesn't fit under it), the next one waits until a
+    /// further `1.0 - ratio` of the capacity has been allocated, rather
+    /// than running before every allocation. So a threshold of `1.0`
+    /// still collects before every allocation, which is useful for
+    /// testing that objects are properly rooted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ratio` is not between `0.0` and `1.0` (inclusive).
+    pub fn set_gc_threshold(&mut self, ratio: f64) {
+        assert!(
+            (0.0..=1.0).contains(&ratio),
+            "GC threshold must be between 0 and 1, got {}",
+            ratio
+        );
 
-        if header.size >= n + header.size() {
-            let residue_size = header.size - n;
-            let residue_addr = (header.next + self.space.len() - residue_size) % self.space.len();
+        self.gc_threshold = ratio;
+        self.gc_trigger = None;
+    }
 
-            let residue_header = Header::new(header.next, residue_size - header.size(), false);
+    /// Sets the size (in bytes) at or above which allocations are placed
+    /// in a separate large-object region, rather than in the main heap.
+    /// Keeping large objects out of the main region's free list prevents
+    /// them from fragmenting it, and large objects are never moved by
+    /// `Heap::compact`. By default, every object is allocated in the main
+    /// region.
+    ///
+    /// # Notes
+    ///
+    /// The large-object region is a simple list of blocks, each
+    /// reserved for a single object. A dead large object's block is
+    /// reused by a later large object that fits in it, but blocks are
+    /// never split or coalesced, and the region is never shrunk.
+    pub fn set_large_object_threshold(&mut self, threshold: usize) {
+        self.large_threshold = Some(threshold);
+    }
 
-            header.size = n;
-            header.next = residue_addr;
+    /// Registers `f` to be called once the object at `addr` is found to
+    /// be unreachable, just before its block is reclaimed. Registering a
+    /// second finalizer for the same object replaces the first.
+    ///
+    /// # Notes
+    ///
+    /// A finalizer is called at most once, and is deregistered when it
+    /// is. Finalizers follow their objects when `Heap::compact` moves
+    /// them.
+    pub fn register_finalizer(&mut self, addr: usize, f: Box<dyn FnOnce()>) {
+        self.finalizers.insert(addr, f);
+    }
 
-            residue_header.write(self, residue_addr);
-        }
+    /// Increments the reference count of the object at `addr`. While its
+    /// count is nonzero, the object is treated as a root.
+    ///
+    /// # Notes
+    ///
+    /// Reference counts are stored in each block's header, so this is
+    /// only available with the `refcount-header` feature.
+    #[cfg(feature = "refcount-header")]
+    pub fn incref(&mut self, addr: usize) {
+        let header_addr = addr - Header::new(0, 0, false).size();
+        let mut header = Header::read(self, header_addr);
+
+        header.refcount += 1;
+        header.write(self, header_addr);
     }
 
-    fn collect(&mut self) {
-        self.mark();
-        self.sweep();
+    /// Decrements the reference count of the object at `addr`. Once its
+    /// count drops to zero, the object is freed immediately (and any
+    /// finalizer registered for it is called), without waiting for a
+    /// collection.
+    ///
+    /// The caller must ensure that nothing else refers to the object
+    /// once its count drops to zero: in particular, the collector won't
+    /// check that it's unreachable. (Weak references to it are cleared,
+    /// though.)
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object's count is already zero.
+    #[cfg(feature = "refcount-header")]
+    pub fn decref(&mut self, addr: usize) {
+        let header_addr = addr - Header::new(0, 0, false).size();
+        let mut header = Header::read(self, header_addr);
+
+        if header.refcount == 0 {
+            panic!("Attempted to decref {}, whose count is zero", addr);
+        }
+
+        header.refcount -= 1;
+        header.write(self, header_addr);
+
+        if header.refcount == 0 {
+            self.free(addr);
+        }
     }
 
-    fn mark(&mut self) {
+    /// Frees the block holding the object at `addr`, coalescing it with
+    /// any free blocks that immediately follow it. As when a block is
+    /// swept, weak references to the object are cleared, and the block
+    /// is dropped from the young generation.
+    #[cfg(feature = "refcount-header")]
+    fn free(&mut self, addr: usize) {
         let header_size = Header::new(0, 0, false).size();
+        let header_addr = addr - header_size;
+        let mut header = Header::read(self, header_addr);
 
-        let mut root_addrs = (*self.get_roots)();
+        if let Some(f) = self.finalizers.remove(&addr) {
+            f();
+        }
 
-        while let Some(root_addr) = root_addrs.pop() {
-            let header_addr = root_addr - header_size;
-            let mut header = Header::read(self, header_addr);
+        let parents: Vec<usize> = self
+            .block_addrs()
+            .into_iter()
+            .chain(self.large_blocks.iter().copied())
+            .filter(|&parent_addr| Header::read(self, parent_addr).allocd)
+            .collect();
+        for parent_addr in parents {
+            for slot_addr in weak_slots(self, parent_addr + header_size) {
+                if Pointer::read(self, slot_addr) == Pointer(addr) {
+                    Pointer(0).write(self, slot_addr);
+                }
+            }
+        }
 
-            if header.marked {
-                continue;
+        if let Some(gens) = &mut self.generations {
+            if gens.young.remove(&header_addr) {
+                gens.young_bytes -= header.size;
             }
+            gens.remembered.remove(&addr);
+        }
 
-            header.marked = true;
-            header.write(self, header_addr);
+        header.allocd = false;
+        self.live_bytes -= header.size;
 
-            root_addrs.append(&mut children(self, root_addr));
+        // Large blocks aren't chained (see `Heap::alloc_large`), and
+        // aren't included in the count of free bytes.
+        if header_addr < LARGE_BASE {
+            self.free_bytes += header.size;
+        }
+
+        while header.next != 0 {
+            let next_header = Header::read(self, header.next);
+            if next_header.allocd {
+                break;
+            }
+
+            // The absorbed block's contents are already counted as free.
+            header.size += header_size + next_header.size;
+            header.next = next_header.next;
+            self.free_bytes += header_size;
         }
+
+        header.write(self, header_addr);
     }
 
-    fn sweep(&mut self) {
-        let mut header: Header;
-        let mut header_addr = 0;
+    /// Returns the collector statistics gathered so far.
+    pub fn gc_stats(&self) -> &GcStats {
+        &self.gc_stats
+    }
 
-        loop {
-            header = Header::read(self, header_addr);
+    /// Clears the collector statistics, e.g. between benchmark phases.
+    pub fn reset_gc_stats(&mut self) {
+        self.gc_stats = GcStats::default();
+    }
 
-            if !header.marked {
-                // Coalesce with following unmarked blocks
-                let mut next = header.next;
-                loop {
-                    let next_header = Header::read(self, next);
-                    if next_header.marked {
-                        break;
+    /// Formats `len` bytes of the heap, starting at `start`, in the
+    /// classic hex dump layout: an offset, 16 bytes in hex, and the same
+    /// bytes as ASCII (with `.` standing in for unprintable bytes). Any
+    /// block headers that begin on a line are listed at its end.
+    pub fn hex_dump(&self, start: usize, len: usize) -> String {
+        let header_addrs = self.block_addrs();
+        let end = (start + len).min(self.space.len());
+
+        let mut out = String::new();
+
+        for line_start in (start..end).step_by(16) {
+            let line_end = (line_start + 16).min(end);
+            let bytes = &self.space[line_start..line_end];
+
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
                     }
+                })
+                .collect();
 
-                    next = next_header.next;
-                }
+            out.push_str(&format!(
+                "{:08x}  {:<47}  |{}|",
+                line_start,
+                hex.join(" "),
+                ascii
+            ));
 
-                header.allocd = false;
-                header.next = next;
+            let headers: Vec<String> = header_addrs
+                .iter()
+                .filter(|&&addr| line_start <= addr && addr < line_end)
+                .map(|addr| format!("{:#x}", addr))
+                .collect();
+            if !headers.is_empty() {
+                out.push_str(&format!("  headers: {}", headers.join(", ")));
             }
 
-            header.marked = false;
-            header.write(self, header_addr);
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Lists every block in the heap, one per line, as
+    /// `(addr, next, size, allocd, marked)`.
+    pub fn dump_headers(&self) -> String {
+        let mut out = String::new();
+
+        for addr in self.block_addrs() {
+            let header = Header::read(self, addr);
+            out.push_str(&format!(
+                "({}, {}, {}, {}, {})\n",
+                addr, header.next, header.size, header.allocd, header.marked
+            ));
+        }
+
+        out
+    }
+
+    /// Returns the backing storage holding `addr`, along with `addr`'s
+    /// offset within it.
+    fn region(&self, addr: usize) -> (&[u8], usize) {
+        if addr >= LARGE_BASE {
+            (&self.large, addr - LARGE_BASE)
+        } else {
+            (&self.space, addr)
+        }
+    }
+
+    fn region_mut(&mut self, addr: usize) -> (&mut [u8], usize) {
+        if addr >= LARGE_BASE {
+            (&mut self.large, addr - LARGE_BASE)
+        } else {
+            (&mut self.space, addr)
+        }
+    }
+
+    /// Returns the addresses of every block header, in chain order.
+    fn block_addrs(&self) -> Vec<usize> {
+        let mut addrs = vec![];
+        let mut header_addr = 0;
 
+        loop {
+            addrs.push(header_addr);
+
+            let header = Header::read(self, header_addr);
             if header.next == 0 {
-                break;
+                return addrs;
             }
 
             header_addr = header.next;
         }
     }
-}
 
-/// Returns the addresses of any child objects that are part of the
-/// parent object stored at `parent_addr`.
-pub fn children<M: Mem>(mem: &M, parent_addr: usize) -> Vec<usize> {
-    match Tag::from(mem.read(parent_addr)) {
-        Tag::Pair => {
-            let prim_size = SchemeObj::Nil.size();
-            let car_addr = parent_addr + 1;
-            let cdr_addr = parent_addr + 1 + prim_size;
+    /// Checks the heap's invariants, returning the first violation found:
+    /// every block must have a valid header, the blocks must exactly
+    /// tile the main region (each block's `next` being the address just
+    /// past its end, and the last block's `next` being 0), and the counts
+    /// of live bytes (in both regions) and free bytes (in the main
+    /// region) must match the blocks.
+    pub fn verify(&self) -> Result<(), HeapError> {
+        let header_size = Header::new(0, 0, false).size();
+        let valid_header = |addr: usize| {
+            let (region, offset) = self.region(addr);
+            offset + header_size <= region.len() && region[offset + header_size - 1] == MAGIC
+        };
+
+        let mut live_bytes = 0;
+        let mut free_bytes = 0;
+        let mut addr = 0;
+
+        loop {
+            if !valid_header(addr) {
+                return Err(HeapError::CorruptHeader(addr));
+            }
+
+            let header = Header::read(self, addr);
+            let end = addr + header_size + header.size;
+            if end > self.space.len() {
+                return Err(HeapError::Overrun { addr, end });
+            }
 
-            let mut children = vec![];
+            if header.allocd {
+                live_bytes += header.size;
+            } else {
+                free_bytes += header.size;
+            }
 
-            if Tag::from(mem.read(car_addr)) == Tag::Box {
-                children.push(usize::read(mem, car_addr + 1));
+            let expected = if end == self.space.len() { 0 } else { end };
+            if header.next != expected {
+                return Err(HeapError::BadNext {
+                    addr,
+                    next: header.next,
+                    expected,
+                });
             }
-            if Tag::from(mem.read(cdr_addr)) == Tag::Box {
-                children.push(usize::read(mem, cdr_addr + 1));
+
+            if header.next == 0 {
+                break;
             }
 
-            children
+            addr = header.next;
         }
-        _ => vec![],
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        for &addr in &self.large_blocks {
+            if !valid_header(addr) {
+                return Err(HeapError::CorruptHeader(addr));
+            }
 
-    #[test]
-    fn initalize() {
-        let mem = Heap::new(32, Box::new(|| vec![]));
-        let header = Header::read(&mem, 0);
-        assert_eq!(header, Header::new(0, 32 - header.size(), false));
-    }
+            let header = Header::read(self, addr);
+            if header.allocd {
+                live_bytes += header.size;
+            }
+        }
 
-    #[test]
-    fn alloc_split() {
-        let size = 128;
-        let mut mem = Heap::new(size, Box::new(|| vec![]));
+        if live_bytes != self.live_bytes {
+            return Err(HeapError::LiveBytes {
+                recorded: self.live_bytes,
+                actual: live_bytes,
+            });
+        }
 
-        let n = 12;
-        let addr = mem.alloc_bytes(n, false);
+        if free_bytes != self.free_bytes {
+            return Err(HeapError::FreeBytes {
+                recorded: self.free_bytes,
+                actual: free_bytes,
+            });
+        }
 
-        let header1 = Header::read(&mem, 0);
-        let header2 = Header::read(&mem, header1.size() + n);
+        Ok(())
+    }
 
-        assert_eq!(addr, header1.size());
-        assert_eq!(header1, Header::new(header1.size() + n, n, true));
-        assert_eq!(
-            header2,
-            Header::new(0, size - n - 2 * header1.size(), false)
-        );
+    /// Returns the size of the heap's main region, in bytes (including
+    /// block headers).
+    pub fn capacity(&self) -> usize {
+        self.space.len()
     }
 
-    #[test]
-    fn alloc_no_split() {
-        let test_header = Header::new(0, 0, false);
-        let n = 43;
+    /// Returns the total writable capacity of all allocated blocks, in
+    /// constant time. This is maintained as blocks are allocated, and
+    /// recomputed whenever the heap is swept (or compacted).
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes
+    }
 
-        let mut mem = Heap::new(test_header.size() + n, Box::new(|| vec![]));
+    /// Returns a histogram mapping each requested allocation size (in
+    /// bytes) to the number of successful allocations of that size over
+    /// the heap's lifetime.
+    pub fn alloc_size_histogram(&self) -> BTreeMap<usize, usize> {
+        self.alloc_sizes.clone()
+    }
 
-        let addr = mem.alloc_bytes(n, false);
+    /// # Notes
+    ///
+    /// Returns the address of the first byte _within_ the allocated
+    /// block, and *not* the address of the block header.
+    fn alloc_bytes(&mut self, n: usize, attempt_collect: bool) -> usize {
+        if matches!(self.large_threshold, Some(threshold) if n >= threshold) {
+            return self.alloc_large(n);
+        }
 
-        let header1 = Header::read(&mem, 0);
+        self.alloc_padded(n, 1, attempt_collect)
+    }
 
-        assert_eq!(addr, header1.size());
-        assert_eq!(header1.next, 0);
+    /// Allocates `n` bytes in the main region, returning an address that
+    /// is a multiple of `align` (which must be a power of two).
+    ///
+    /// # Notes
+    ///
+    /// Any padding needed to align the block is split off into a free
+    /// block of its own, immediately before it, so the padding is freed
+    /// and coalesced like any other block. Large objects aren't aligned,
+    /// so this never allocates in the large-object region.
+    ///
+    /// Addresses are offsets into the heap, so they're aligned relative
+    /// to its start. Alignment isn't preserved by `Heap::compact`, which
+    /// slides blocks together without regard for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` isn't a power of two.
+    pub fn alloc_aligned(&mut self, n: usize, align: usize) -> usize {
+        if !align.is_power_of_two() {
+            panic!("alignment {} isn't a power of two", align);
+        }
+
+        self.alloc_padded(n, align, true)
     }
 
-    #[test]
+    fn alloc_padded(&mut self, n: usize, align: usize, mut attempt_collect: bool) -> usize {
+        if attempt_collect && self.nursery_full(n) {
+            self.collect_minor();
+        }
+
+        if attempt_collect && self.below_gc_threshold() {
+            self.collect();
+            attempt_collect = false;
+        }
+
+        let mut header: Header;
+        let mut header_addr = 0;
+        let mut best: Option<(usize, usize)> = None;
+
+        loop {
+            header = Header::read(self, header_addr);
+
+            let padding = padding(header_addr, align);
+            let fits = !header.allocd && header.size >= padding + n;
+            if fits && best.is_none_or(|(_, size)| header.size < size) {
+                best = Some((header_addr, header.size));
+
+                // There's no need to keep looking if we'll take the first
+                // fit, or if we can't possibly find a snugger one.
+                if self.strategy == AllocStrategy::FirstFit || header.size == n {
+                    break;
+                }
+            }
+
+            if header.next == 0 {
+                if best.is_some() {
+                    break;
+                }
+
+                if attempt_collect {
+                    self.collect();
+                    return self.alloc_padded(n, align, false);
+                }
+
+                // Splice the newly-grown space into the chain, in place
+                // of the 0 sentinel at the end of the last block. We
+                // reserve room for the most padding it could need.
+                let max_padding = if align > 1 { align + header.size() } else { 0 };
+                if let Some(new_addr) = self.grow(max_padding + n) {
+                    header.next = new_addr;
+                    header.write(self, header_addr);
+                    header_addr = new_addr;
+                    continue;
+                }
+
+                panic!("Unable to allocate: out of memory");
+            }
+
+            header_addr = header.next;
+        }
+
+        // We only leave the loop once we've chosen a block.
+        let (mut header_addr, _) = best.unwrap();
+        let mut header = Header::read(self, header_addr);
+
+        let padding = padding(header_addr, align);
+        if padding > 0 {
+            let padding_header = Header::new(header_addr + padding, padding - header.size(), false);
+            padding_header.write(self, header_addr);
+            self.free_bytes -= header.size();
+
+            header_addr += padding;
+            header.size -= padding;
+            header.write(self, header_addr);
+        }
+
+        self.alloc_block(header_addr, &mut header, n);
+        // Blocks allocated while a collection is in progress are treated
+        // as live (black), as the marker won't revisit the roots to find
+        // them.
+        header.marked = self.gray.is_some();
+        header.write(self, header_addr);
+        *self.alloc_sizes.entry(n).or_insert(0) += 1;
+        self.note_young(header_addr, header.size);
+        header_addr + header.size()
+    }
+
+    /// Allocates `n` bytes in the large-object region, reusing the first
+    /// free block that's big enough, or else reserving a new block at the
+    /// end of the region.
+    ///
+    /// # Notes
+    ///
+    /// Large blocks aren't chained together (the region's addresses don't
+    /// fit in a compact header's `next`), so each header's `next` is 0 and
+    /// the blocks are tracked in `large_blocks` instead.
+    fn alloc_large(&mut self, n: usize) -> usize {
+        let header_size = Header::new(0, 0, false).size();
+
+        let free_addr = self.large_blocks.iter().copied().find(|&addr| {
+            let header = Header::read(self, addr);
+            !header.allocd && header.size >= n
+        });
+
+        let header_addr = match free_addr {
+            Some(addr) => addr,
+            None => {
+                let addr = LARGE_BASE + self.large.len();
+                self.large.resize(self.large.len() + header_size + n, 0);
+                Header::new(0, n, false).write(self, addr);
+                self.large_blocks.push(addr);
+                addr
+            }
+        };
+
+        let mut header = Header::read(self, header_addr);
+        header.allocd = true;
+        header.marked = self.gray.is_some();
+        header.write(self, header_addr);
+
+        self.live_bytes += header.size;
+        *self.alloc_sizes.entry(n).or_insert(0) += 1;
+        self.note_young(header_addr, header.size);
+
+        header_addr + header_size
+    }
+
+    /// Returns `true` if allocating `n` more bytes would exceed a
+    /// generational heap's nursery.
+    fn nursery_full(&self, n: usize) -> bool {
+        match &self.generations {
+            Some(gens) => gens.young_bytes + n > gens.young_size,
+            None => false,
+        }
+    }
+
+    /// Records the freshly-allocated block at `header_addr` as young.
+    fn note_young(&mut self, header_addr: usize, size: usize) {
+        if let Some(gens) = &mut self.generations {
+            gens.young.insert(header_addr);
+            gens.young_bytes += size;
+        }
+    }
+
+    /// Promotes every young block (after a collection, only survivors
+    /// remain), leaving no pointers from old objects to young ones.
+    fn promote_all(&mut self) {
+        if let Some(gens) = &mut self.generations {
+            gens.young.clear();
+            gens.young_bytes = 0;
+            gens.remembered.clear();
+        }
+    }
+
+    /// Extends the heap's backing storage (doubling it, as often as
+    /// necessary, without exceeding `max_size`) so that a fresh block
+    /// can hold `n` bytes. Returns the address of the new block's
+    /// header, or `None` if the heap can't grow large enough.
+    ///
+    /// # Notes
+    ///
+    /// The new block is the last in the chain (its `next` is 0), but it
+    /// is up to the caller to link the previous last block to it.
+    fn grow(&mut self, n: usize) -> Option<usize> {
+        let header_size = Header::new(0, 0, false).size();
+        let old_len = self.space.len();
+        let mut new_len = old_len;
+
+        while new_len - old_len < header_size + n {
+            if new_len >= self.max_size {
+                return None;
+            }
+
+            new_len = (new_len * 2).min(self.max_size);
+        }
+
+        self.space.resize(new_len, 0);
+        Header::new(0, new_len - old_len - header_size, false).write(self, old_len);
+        self.free_bytes += new_len - old_len - header_size;
+
+        Some(old_len)
+    }
+
+    /// Releases the free space at the end of the main region, if its
+    /// last block is free. The last block is dropped from the chain (or,
+    /// if it's the only block, emptied), and the region is truncated to
+    /// end where it began. Live objects are never moved, so this only
+    /// helps if the tail of the heap is free; see `Heap::compact`.
+    ///
+    /// The heap may grow again, up to its maximum size, as needed.
+    pub fn shrink_to_fit(&mut self) {
+        let header_size = Header::new(0, 0, false).size();
+
+        let mut prev_addr = None;
+        let mut header_addr = 0;
+        let mut header = Header::read(self, header_addr);
+        while header.next != 0 {
+            prev_addr = Some(header_addr);
+            header_addr = header.next;
+            header = Header::read(self, header_addr);
+        }
+
+        if header.allocd {
+            return;
+        }
+
+        self.free_bytes -= header.size;
+        let new_len = match prev_addr {
+            Some(prev_addr) => {
+                let mut prev = Header::read(self, prev_addr);
+                prev.next = 0;
+                prev.write(self, prev_addr);
+                header_addr
+            }
+            None => {
+                header.size = 0;
+                header.write(self, header_addr);
+                header_size
+            }
+        };
+
+        self.space.truncate(new_len);
+        self.space.shrink_to_fit();
+    }
+
+    /// Marks the block headed by `header` (located at `header_addr`) as
+    /// allocated, and -- if the block is large enough -- splits it into
+    /// two blocks where the second is unallocated.
+    ///
+    /// # Notes
+    ///
+    /// The residue block begins immediately after the `n` bytes we've
+    /// allocated, so its address is derived from the block's own
+    /// address. (Deriving it from `header.next` breaks down for the
+    /// final block, whose `next` wraps around to 0.)
+    ///
+    /// We still need to write our updated (original) header to memory,
+    /// via `header.write(..)`, in order to persist the changes we've
+    /// made to it. At the moment, we do this in the caller
+    /// (`self.alloc_bytes`), but it may make more sense to do it here.
+    fn alloc_block(&mut self, header_addr: usize, header: &mut Header, n: usize) {
+        header.allocd = true;
+
+        if header.size >= n + header.size() {
+            let residue_size = header.size - n;
+            let residue_addr = header_addr + header.size() + n;
+
+            let residue_header = Header::new(header.next, residue_size - header.size(), false);
+
+            header.size = n;
+            header.next = residue_addr;
+
+            residue_header.write(self, residue_addr);
+            self.free_bytes -= header.size();
+        }
+
+        self.live_bytes += header.size;
+        self.free_bytes -= header.size;
+    }
+
+    /// Returns `true` if the free space remaining in the heap has dropped
+    /// below the configured GC threshold (and, following a collection
+    /// that couldn't free enough to rise above it, below the trigger
+    /// that it set).
+    fn below_gc_threshold(&self) -> bool {
+        if self.gc_threshold == 0.0 {
+            return false;
+        }
+
+        let capacity = self.space.len() as f64;
+
+        (self.free_bytes as f64) < self.gc_threshold * capacity
+            && self
+                .gc_trigger
+                .is_none_or(|trigger| self.free_bytes <= trigger)
+    }
+
+    /// Returns the total writable capacity of all unallocated blocks in
+    /// the main region, in constant time. Like `Heap::live_bytes`, this
+    /// is maintained as blocks are allocated and freed.
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    /// Counts the free bytes in the main region by walking the entire
+    /// chain of blocks.
+    fn count_free_bytes(&self) -> usize {
+        let mut free = 0;
+        let mut header_addr = 0;
+
+        loop {
+            let header = Header::read(self, header_addr);
+
+            if !header.allocd {
+                free += header.size;
+            }
+
+            if header.next == 0 {
+                return free;
+            }
+
+            header_addr = header.next;
+        }
+    }
+
+    /// Runs a full mark-and-sweep collection, freeing every block that
+    /// isn't reachable from the roots returned by `get_roots`.
+    ///
+    /// If an incremental collection is in progress, it is run to
+    /// completion.
+    pub fn collect(&mut self) {
+        let start = Instant::now();
+
+        self.mark_step(usize::MAX);
+        self.finish_collection(start);
+    }
+
+    /// Runs a full collection, like `Heap::collect`, returning the
+    /// address of every object that it freed. This is intended for
+    /// debugging (e.g. asserting exactly which objects died), so it's
+    /// only available in debug builds.
+    #[cfg(debug_assertions)]
+    pub fn collect_report(&mut self) -> Vec<usize> {
+        let start = Instant::now();
+        let header_size = Header::new(0, 0, false).size();
+
+        self.mark_step(usize::MAX);
+
+        let dead: Vec<usize> = self
+            .block_addrs()
+            .into_iter()
+            .chain(self.large_blocks.iter().copied())
+            .filter(|&addr| {
+                let header = Header::read(self, addr);
+                header.allocd && !header.marked
+            })
+            .map(|addr| addr + header_size)
+            .collect();
+
+        self.finish_collection(start);
+
+        dead
+    }
+
+    /// Runs a minor collection, which only frees young blocks, promoting
+    /// those that survive. Rather than tracing the entire heap, old
+    /// objects are assumed to be live, and aren't scanned: instead, the
+    /// children of old objects that have been made to point at young
+    /// ones (as recorded by the write barrier) are treated as roots.
+    ///
+    /// On a heap that isn't generational, or if an incremental collection
+    /// is in progress, this runs a full collection instead.
+    pub fn collect_minor(&mut self) {
+        let (young, remembered) = match &self.generations {
+            Some(gens) if self.gray.is_none() => (gens.young.clone(), gens.remembered.clone()),
+            _ => return self.collect(),
+        };
+
+        let start = Instant::now();
+
+        // Marking old blocks up front keeps the marker from scanning
+        // them, and the sweep from freeing them.
+        let blocks: Vec<usize> = self
+            .block_addrs()
+            .into_iter()
+            .chain(self.large_blocks.iter().copied())
+            .collect();
+        for header_addr in blocks {
+            let mut header = Header::read(self, header_addr);
+
+            if header.allocd && !young.contains(&header_addr) {
+                header.marked = true;
+                header.write(self, header_addr);
+            }
+        }
+
+        let mut roots = self.roots();
+        for parent_addr in remembered {
+            roots.append(&mut children(self, parent_addr));
+        }
+
+        self.gray = Some(roots);
+        self.mark_step(usize::MAX);
+        self.finish_collection(start);
+
+        self.gc_stats.minor_collections += 1;
+    }
+
+    /// Performs at most `budget` units of marking work (one unit per
+    /// object visited), starting a new collection cycle if one isn't
+    /// already in progress. Once marking is complete, the heap is swept
+    /// and `CollectProgress::Done` is returned. This allows a host (an
+    /// async executor, for instance) to interleave collection with other
+    /// work rather than pausing for a full `collect`.
+    ///
+    /// # Notes
+    ///
+    /// The roots are gathered once, at the start of the cycle, and any
+    /// blocks allocated while the cycle is in progress survive it.
+    /// Pointers stored by `set_car` and `set_cdr` between steps are
+    /// caught by the write barrier, which shades their targets gray. But
+    /// the mutator must not otherwise introduce new references to
+    /// unmarked objects (for instance, by adding a root, or by writing a
+    /// pointer directly), since the marker won't see them.
+    ///
+    /// The recorded `last_pause` is the duration of the final step.
+    pub fn collect_incremental(&mut self, budget: usize) -> CollectProgress {
+        let start = Instant::now();
+
+        if self.mark_step(budget) {
+            self.finish_collection(start);
+            CollectProgress::Done
+        } else {
+            CollectProgress::More
+        }
+    }
+
+    /// Returns `true` if an incremental collection cycle has been started
+    /// (by `Heap::collect_incremental`), but not yet completed.
+    pub fn collection_in_progress(&self) -> bool {
+        self.gray.is_some()
+    }
+
+    /// Sweeps the heap after marking has completed, ending the current
+    /// collection cycle and updating the collector's statistics.
+    fn finish_collection(&mut self, start: Instant) {
+        let free_before = self.free_bytes();
+        self.clear_dead_weak_refs();
+        self.run_finalizers();
+        self.sweep();
+        self.record_collection(start, free_before);
+    }
+
+    /// Ends the current collection cycle, updating the collector's
+    /// statistics given the amount of free space before it reclaimed
+    /// anything.
+    fn record_collection(&mut self, start: Instant, free_before: usize) {
+        let free_after = self.free_bytes();
+
+        // If the live data doesn't fit under the threshold, wait for
+        // another `1.0 - gc_threshold` of the capacity to be allocated
+        // before collecting again (see `Heap::set_gc_threshold`).
+        let capacity = self.space.len() as f64;
+        self.gc_trigger = if (free_after as f64) < self.gc_threshold * capacity {
+            let gap = ((1.0 - self.gc_threshold) * capacity) as usize;
+            Some(free_after.saturating_sub(gap))
+        } else {
+            None
+        };
+
+        self.gray = None;
+
+        self.gc_stats.collections += 1;
+        self.gc_stats.bytes_reclaimed += free_after - free_before;
+        self.gc_stats.last_pause = start.elapsed();
+    }
+
+    /// Runs a full collection that, rather than sweeping dead blocks
+    /// into the free list, slides every live block towards the start of
+    /// the heap, leaving a single free block at the end. Pointers between
+    /// heap objects (and the constants pool) are updated to match.
+    ///
+    /// Returns a forwarding map from the old address of every live
+    /// object to its new address.
+    ///
+    /// # Notes
+    ///
+    /// Compaction invalidates any addresses held outside of the heap,
+    /// including those returned by `get_roots`: an embedder holding
+    /// such addresses *must* remap them through the returned map
+    /// before using them again.
+    pub fn compact(&mut self) -> HashMap<usize, usize> {
+        let start = Instant::now();
+        let header_size = Header::new(0, 0, false).size();
+
+        self.mark_step(usize::MAX);
+        let free_before = self.free_bytes();
+        self.clear_dead_weak_refs();
+        self.run_finalizers();
+
+        // Assign each live block its new location.
+        let mut live = vec![];
+        let mut forwarding = HashMap::new();
+        let mut free_addr = 0;
+
+        for header_addr in self.block_addrs() {
+            let header = Header::read(self, header_addr);
+
+            if header.marked {
+                live.push((header_addr, header.size));
+                forwarding.insert(header_addr + header_size, free_addr + header_size);
+                free_addr += header_size + header.size;
+            }
+        }
+
+        // Update every pointer (weak or not) held by a live object, while
+        // the objects are still at their old locations. Large objects
+        // don't move, so pointers to them are left as-is (though they may
+        // themselves point into the main region). Cleared weak pointers
+        // hold 0, which is never forwarded.
+        let live_large = self
+            .large_blocks
+            .iter()
+            .copied()
+            .filter(|&addr| Header::read(self, addr).marked);
+        let parents: Vec<usize> = live
+            .iter()
+            .map(|&(header_addr, _)| header_addr)
+            .chain(live_large)
+            .collect();
+
+        for header_addr in parents {
+            let obj_addr = header_addr + header_size;
+            let mut slots = pointer_slots(self, obj_addr);
+            slots.append(&mut weak_slots(self, obj_addr));
+
+            for slot_addr in slots {
+                let Pointer(child) = Pointer::read(self, slot_addr);
+                Pointer(forwarding.get(&child).copied().unwrap_or(child)).write(self, slot_addr);
+            }
+        }
+
+        // Slide each block into place. Blocks only ever move towards the
+        // start of the heap, and are moved in address order, so we never
+        // overwrite a block we've yet to move.
+        let mut new_addr = 0;
+        self.live_bytes = 0;
+        for (i, &(header_addr, mut size)) in live.iter().enumerate() {
+            let next = new_addr + header_size + size;
+            let is_last = i + 1 == live.len();
+
+            self.space
+                .copy_within(header_addr..header_addr + header_size + size, new_addr);
+
+            // If there isn't room for a free block after the last live
+            // block, it absorbs the remaining bytes.
+            let remaining = self.space.len() - next;
+            let next = if !is_last {
+                next
+            } else if remaining < header_size {
+                size += remaining;
+                0
+            } else {
+                Header::new(0, remaining - header_size, false).write(self, next);
+                next
+            };
+
+            // The header was copied along with the block, so its
+            // reference count carries over.
+            let refcount = Header::read(self, new_addr).refcount;
+            Header {
+                refcount,
+                ..Header::new(next, size, true)
+            }
+            .write(self, new_addr);
+            self.live_bytes += size;
+            new_addr = next;
+        }
+
+        if live.is_empty() {
+            Header::new(0, self.space.len() - header_size, false).write(self, 0);
+        }
+        self.free_bytes = self.count_free_bytes();
+
+        // Large objects aren't in `forwarding`, since they don't move.
+        for addr in self.constants.values_mut().chain(&mut self.temp_roots) {
+            *addr = forwarding.get(addr).copied().unwrap_or(*addr);
+        }
+
+        self.finalizers = self
+            .finalizers
+            .drain()
+            .map(|(addr, f)| (forwarding.get(&addr).copied().unwrap_or(addr), f))
+            .collect();
+
+        self.sweep_large();
+        self.promote_all();
+
+        self.record_collection(start, free_before);
+
+        forwarding
+    }
+
+    /// Runs a compacting collection (see `compact`), returning the
+    /// forwarding map from old to new addresses so that an embedder can
+    /// update any addresses it holds outside of the heap.
+    pub fn collect_compact_with_map(&mut self) -> HashMap<usize, usize> {
+        self.compact()
+    }
+
+    /// Returns every root: those returned by `get_roots`, along with the
+    /// constants pool, any temporary roots, and any objects with nonzero
+    /// reference counts.
+    fn roots(&self) -> Vec<usize> {
+        let mut roots = (*self.get_roots)();
+        roots.extend(self.constants.values());
+        roots.extend(&self.temp_roots);
+        roots.extend(self.refcounted());
+        roots
+    }
+
+    /// Returns the address of every object with a nonzero reference
+    /// count (see `Heap::incref`).
+    fn refcounted(&self) -> Vec<usize> {
+        if !cfg!(feature = "refcount-header") {
+            return vec![];
+        }
+
+        let header_size = Header::new(0, 0, false).size();
+
+        self.block_addrs()
+            .into_iter()
+            .chain(self.large_blocks.iter().copied())
+            .filter(|&addr| {
+                let header = Header::read(self, addr);
+                header.allocd && header.refcount > 0
+            })
+            .map(|addr| addr + header_size)
+            .collect()
+    }
+
+    /// Returns the address of every object reachable from the roots, in
+    /// ascending order. This performs the same traversal as marking, but
+    /// leaves the heap untouched.
+    pub fn live_addresses(&self) -> Vec<usize> {
+        let mut live: Vec<usize> = self.reachable(self.roots()).into_iter().collect();
+        live.sort_unstable();
+        live
+    }
+
+    /// Returns the number of bytes (including block headers) that would
+    /// be freed if `root_addr` were no longer a root: that is, the total
+    /// size of the objects reachable from `root_addr`, but not from any
+    /// of the other roots.
+    pub fn retained_bytes(&self, root_addr: usize) -> usize {
+        let header_size = Header::new(0, 0, false).size();
+
+        let others: Vec<usize> = self
+            .roots()
+            .into_iter()
+            .filter(|&addr| addr != root_addr)
+            .collect();
+        let kept = self.reachable(others);
+
+        self.reachable(vec![root_addr])
+            .into_iter()
+            .filter(|addr| !kept.contains(addr))
+            .map(|addr| header_size + Header::read(self, addr - header_size).size)
+            .sum()
+    }
+
+    /// Returns the address of every object reachable from `roots`,
+    /// without marking them.
+    fn reachable(&self, roots: Vec<usize>) -> HashSet<usize> {
+        let mut seen = HashSet::new();
+        let mut pending = roots;
+
+        while let Some(addr) = pending.pop() {
+            if seen.insert(addr) {
+                pending.append(&mut children(self, addr));
+            }
+        }
+
+        seen
+    }
+
+    /// Marks at most `budget` objects reachable from the roots, keeping
+    /// the remaining (gray) addresses around for the next step. Returns
+    /// `true` once every reachable object has been marked.
+    fn mark_step(&mut self, budget: usize) -> bool {
+        let header_size = Header::new(0, 0, false).size();
+
+        let mut root_addrs = match self.gray.take() {
+            Some(gray) => gray,
+            None => self.roots(),
+        };
+
+        for _ in 0..budget {
+            let root_addr = match root_addrs.pop() {
+                Some(root_addr) => root_addr,
+                None => break,
+            };
+
+            let header_addr = root_addr - header_size;
+            let mut header = Header::read(self, header_addr);
+
+            if header.marked {
+                continue;
+            }
+
+            header.marked = true;
+            header.write(self, header_addr);
+            self.gc_stats.objects_scanned += 1;
+
+            root_addrs.append(&mut children(self, root_addr));
+        }
+
+        let done = root_addrs.is_empty();
+        self.gray = Some(root_addrs);
+        done
+    }
+
+    /// Clears every weak pointer held by a marked object whose target
+    /// wasn't marked, so that it doesn't dangle once the target is
+    /// swept. This must run after marking completes, but before the
+    /// marks are cleared.
+    fn clear_dead_weak_refs(&mut self) {
+        let header_size = Header::new(0, 0, false).size();
+
+        let live: Vec<usize> = self
+            .block_addrs()
+            .into_iter()
+            .chain(self.large_blocks.iter().copied())
+            .filter(|&addr| Header::read(self, addr).marked)
+            .collect();
+
+        for header_addr in live {
+            for slot_addr in weak_slots(self, header_addr + header_size) {
+                let Pointer(target_addr) = Pointer::read(self, slot_addr);
+
+                if target_addr != 0 && !Header::read(self, target_addr - header_size).marked {
+                    Pointer(0).write(self, slot_addr);
+                }
+            }
+        }
+    }
+
+    /// Calls (and deregisters) the finalizer of every unmarked object.
+    /// Like `clear_dead_weak_refs`, this must run after marking completes,
+    /// but before the marks are cleared.
+    fn run_finalizers(&mut self) {
+        let header_size = Header::new(0, 0, false).size();
+
+        let dead: Vec<usize> = self
+            .finalizers
+            .keys()
+            .copied()
+            .filter(|&addr| !Header::read(self, addr - header_size).marked)
+            .collect();
+
+        for addr in dead {
+            if let Some(f) = self.finalizers.remove(&addr) {
+                f();
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        let mut header: Header;
+        let mut header_addr = 0;
+
+        self.live_bytes = 0;
+        self.free_bytes = 0;
+
+        loop {
+            header = Header::read(self, header_addr);
+
+            if !header.marked {
+                // Coalesce with following unmarked blocks, stopping at
+                // the end of the chain (where `next` wraps back to 0).
+                let mut next = header.next;
+                while next != 0 {
+                    let next_header = Header::read(self, next);
+                    if next_header.marked {
+                        break;
+                    }
+
+                    header.size += next_header.size() + next_header.size;
+                    next = next_header.next;
+                }
+
+                header.allocd = false;
+                header.next = next;
+            }
+
+            if header.allocd {
+                self.live_bytes += header.size;
+            } else {
+                self.free_bytes += header.size;
+            }
+
+            header.marked = false;
+            header.write(self, header_addr);
+
+            if header.next == 0 {
+                break;
+            }
+
+            header_addr = header.next;
+        }
+
+        self.sweep_large();
+        self.promote_all();
+    }
+
+    /// Frees every unmarked block in the large-object region, clearing
+    /// the marks of the rest.
+    fn sweep_large(&mut self) {
+        for i in 0..self.large_blocks.len() {
+            let header_addr = self.large_blocks[i];
+            let mut header = Header::read(self, header_addr);
+
+            if !header.marked {
+                header.allocd = false;
+            }
+
+            if header.allocd {
+                self.live_bytes += header.size;
+            }
+
+            header.marked = false;
+            header.write(self, header_addr);
+        }
+    }
+}
+
+/// Ensures that a main region of `size` bytes stays below `LARGE_BASE`,
+/// so that every address in it can be told apart from those of large
+/// objects (and fits in a pointer).
+fn check_max_size(size: usize) {
+    if size > LARGE_BASE {
+        panic!("heap size {} too large; maximum is {}", size, LARGE_BASE);
+    }
+}
+
+/// Returns the number of bytes to skip past the block at `header_addr`
+/// so that the block's contents begin at a multiple of `align`. Any
+/// nonzero padding is large enough to hold the header of a free block.
+fn padding(header_addr: usize, align: usize) -> usize {
+    let header_size = Header::new(0, 0, false).size();
+    let addr = header_addr + header_size;
+
+    let mut padding = (align - addr % align) % align;
+    while padding > 0 && padding < header_size {
+        padding += align;
+    }
+
+    padding
+}
+
+/// Returns the addresses of any child objects that are part of the
+/// parent object stored at `parent_addr`.
+pub fn children<M: Mem>(mem: &M, parent_addr: usize) -> Vec<usize> {
+    pointer_slots(mem, parent_addr)
+        .into_iter()
+        .map(|slot_addr| Pointer::read(mem, slot_addr).0)
+        .collect()
+}
+
+/// Returns the addresses at which the object stored at `parent_addr`
+/// holds pointers to its children (that is, the locations just past the
+/// tag of each of its boxes).
+///
+/// # Notes
+///
+/// Weak boxes aren't included, as they don't keep their targets alive
+/// (see `weak_slots`).
+pub fn pointer_slots<M: Mem>(mem: &M, parent_addr: usize) -> Vec<usize> {
+    tagged_slots(mem, parent_addr, Tag::Box)
+}
+
+/// Returns the addresses at which the object stored at `parent_addr`
+/// holds weak pointers: either within a standalone weak box, or within
+/// the weak boxes of a pair or vector.
+pub fn weak_slots<M: Mem>(mem: &M, parent_addr: usize) -> Vec<usize> {
+    match Tag::from(mem.read(parent_addr)) {
+        Tag::WeakBox => vec![parent_addr + 1],
+        _ => tagged_slots(mem, parent_addr, Tag::WeakBox),
+    }
+}
+
+/// Returns the locations just past the tag of each of the boxes tagged
+/// with `box_tag` in the object stored at `parent_addr`.
+fn tagged_slots<M: Mem>(mem: &M, parent_addr: usize, box_tag: Tag) -> Vec<usize> {
+    match Tag::from(mem.read(parent_addr)) {
+        Tag::Pair => {
+            let car_addr = parent_addr + 1;
+            let cdr_addr = parent_addr + 1 + BOX_SIZE;
+
+            let mut slots = vec![];
+
+            if Tag::from(mem.read(car_addr)) == box_tag {
+                slots.push(car_addr + 1);
+            }
+            if Tag::from(mem.read(cdr_addr)) == box_tag {
+                slots.push(cdr_addr + 1);
+            }
+
+            slots
+        }
+        Tag::Vector => (0..usize::read(mem, parent_addr + 1))
+            .map(|i| parent_addr + 1 + size_of::<usize>() + i * BOX_SIZE)
+            .filter(|&box_addr| Tag::from(mem.read(box_addr)) == box_tag)
+            .map(|box_addr| box_addr + 1)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::redundant_closure)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn initalize() {
+        let mem = Heap::new(32, Box::new(|| vec![]));
+        let header = Header::read(&mem, 0);
+        assert_eq!(header, Header::new(0, 32 - header.size(), false));
+    }
+
+    #[test]
+    fn alloc_split() {
+        let size = 128;
+        let mut mem = Heap::new(size, Box::new(|| vec![]));
+
+        let n = 12;
+        let addr = mem.alloc_bytes(n, false);
+
+        let header1 = Header::read(&mem, 0);
+        let header2 = Header::read(&mem, header1.size() + n);
+
+        assert_eq!(addr, header1.size());
+        assert_eq!(header1, Header::new(header1.size() + n, n, true));
+        assert_eq!(
+            header2,
+            Header::new(0, size - n - 2 * header1.size(), false)
+        );
+    }
+
+    #[test]
+    fn alloc_split_last_block() {
+        let size = 128;
+        let mut mem = Heap::new(size, Box::new(Vec::new));
+
+        let header_size = Header::new(0, 0, false).size();
+        let (n1, n2) = (12, 20);
+
+        mem.alloc_bytes(n1, false);
+        // The second allocation splits the final block in the chain,
+        // which doesn't start at address 0 and whose `next` is 0.
+        let addr = mem.alloc_bytes(n2, false);
+
+        let header2_addr = header_size + n1;
+        let header3_addr = header2_addr + header_size + n2;
+
+        let header2 = Header::read(&mem, header2_addr);
+        let header3 = Header::read(&mem, header3_addr);
+
+        assert_eq!(addr, header2_addr + header_size);
+        assert_eq!(header2, Header::new(header3_addr, n2, true));
+        assert_eq!(
+            header3,
+            Header::new(0, size - n1 - n2 - 3 * header_size, false)
+        );
+    }
+
+    #[test]
+    fn alloc_split_reused_block() {
+        let size = 256;
+        let mut mem = Heap::new(size, Box::new(Vec::new));
+
+        let header_size = Header::new(0, 0, false).size();
+
+        // Free a large block in the middle of the chain, then split it.
+        let first = mem.alloc_bytes(12, false);
+        mem.alloc_bytes(40, false);
+        let third = mem.alloc_bytes(4, false);
+
+        let roots = vec![first, third];
+        mem.get_roots = Box::new(move || roots.clone());
+        mem.collect();
+
+        let addr = mem.alloc_bytes(8, false);
+
+        let header2_addr = header_size + 12;
+        let residue_addr = header2_addr + header_size + 8;
+        let header3_addr = third - header_size;
+
+        assert_eq!(addr, header2_addr + header_size);
+        assert_eq!(
+            Header::read(&mem, header2_addr),
+            Header::new(residue_addr, 8, true)
+        );
+        assert_eq!(
+            Header::read(&mem, residue_addr),
+            Header::new(header3_addr, 40 - 8 - header_size, false)
+        );
+    }
+
+    #[test]
+    fn alloc_aligned() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        mem.alloc_bytes(3, false);
+        let addr = mem.alloc_aligned(8, 16);
+        assert_eq!(addr % 16, 0);
+        mem.verify().unwrap();
+
+        // The padding is a free block, which coalesces once the aligned
+        // block is freed.
+        mem.collect();
+        assert_eq!(mem.block_addrs(), vec![0]);
+
+        let mut mem = Heap::new_growable(32, 512, Box::new(Vec::new));
+        let addr = mem.alloc_aligned(20, 64);
+        assert_eq!(addr % 64, 0);
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn alloc_no_split() {
+        let test_header = Header::new(0, 0, false);
+        let n = 43;
+
+        let mut mem = Heap::new(test_header.size() + n, Box::new(|| vec![]));
+
+        let addr = mem.alloc_bytes(n, false);
+
+        let header1 = Header::read(&mem, 0);
+
+        assert_eq!(addr, header1.size());
+        assert_eq!(header1.next, 0);
+    }
+
+    #[test]
+    fn collect_coalesces_into_one_block() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        for n in [8, 16, 24, 9, 40].iter() {
+            mem.alloc_bytes(*n, false);
+        }
+        assert_eq!(mem.block_addrs().len(), 6);
+
+        mem.collect();
+        mem.verify().unwrap();
+
+        let header = Header::read(&mem, 0);
+        assert_eq!(mem.block_addrs(), vec![0]);
+        assert_eq!(header, Header::new(0, 256 - header.size(), false));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn collect_report() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let kept = mem.alloc_bytes(8, false);
+        let dropped = mem.alloc_bytes(8, false);
+
+        mem.get_roots = Box::new(move || vec![kept]);
+
+        assert_eq!(mem.collect_report(), vec![dropped]);
+        assert_eq!(mem.collect_report(), vec![]);
+    }
+
+    #[test]
+    fn temp_root_survives_collection() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let addr = mem.alloc_bytes(8, false);
+        mem.push_temp_root(addr);
+
+        let header_size = Header::new(0, 0, false).size();
+        mem.collect();
+        assert!(Header::read(&mem, addr - header_size).allocd);
+
+        assert_eq!(mem.pop_temp_root(), Some(addr));
+        mem.collect();
+        assert!(!Header::read(&mem, addr - header_size).allocd);
+    }
+
+    #[test]
+    fn minor_collection_skips_old_objects() {
+        use crate::data::SchemeObj::*;
+        use crate::list::alloc_list;
+
+        let mut mem = Heap::new_generational(256, 2048, Box::new(Vec::new));
+
+        let old = alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        mem.get_roots = Box::new(move || vec![old]);
+        mem.collect();
+        mem.reset_gc_stats();
+
+        let survivor = mem.alloc_obj(&Number(4));
+        mem.get_roots = Box::new(move || vec![old, survivor]);
+        for n in 0..100 {
+            mem.alloc_obj(&Number(n));
+        }
+        mem.verify().unwrap();
+
+        // Only the survivor was ever scanned (by the first minor
+        // collection, which promoted it).
+        let stats = mem.gc_stats();
+        assert!(stats.minor_collections > 1);
+        assert_eq!(stats.collections, stats.minor_collections);
+        assert_eq!(stats.objects_scanned, 1);
+
+        let expected = alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        assert!(crate::data::scheme_equal(&mem, old, expected));
+        assert!(matches!(SchemeObj::read(&mem, survivor), Number(4)));
+    }
+
+    #[test]
+    fn nested_allocs_fill_nursery() {
+        use crate::data::SchemeObj::*;
+
+        let list = || Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Pair {
+                car: Box::new(Number(2)),
+                cdr: Box::new(Nil),
+            }),
+        };
+
+        // The nursery fills up part way through writing most of the
+        // lists, so minor collections run (and promote the partly
+        // written list) while its children are still being allocated.
+        let mut mem = Heap::new_generational(64, 4096, Box::new(Vec::new));
+
+        let mut addrs = vec![];
+        for _ in 0..10 {
+            let addr = mem.alloc_obj(&list());
+            mem.push_temp_root(addr);
+            addrs.push(addr);
+        }
+
+        assert!(mem.gc_stats().minor_collections > 0);
+        mem.verify().unwrap();
+        for addr in addrs {
+            assert_eq!(SchemeObj::read(&mem, addr), list());
+        }
+    }
+
+    #[test]
+    fn write_barrier_remembers_old_to_young() {
+        use crate::data::{car, set_car, SchemeObj::*};
+        use crate::list::alloc_list;
+
+        let mut mem = Heap::new_generational(256, 2048, Box::new(Vec::new));
+
+        let old = alloc_list(&mut mem, &[Number(1)]);
+        mem.get_roots = Box::new(move || vec![old]);
+        mem.collect();
+
+        // The young number is only reachable through the old pair.
+        let young = mem.alloc_obj(&Number(2));
+        set_car(&mut mem, old, young);
+        mem.collect_minor();
+
+        let header_size = Header::new(0, 0, false).size();
+        assert!(Header::read(&mem, young - header_size).allocd);
+        assert!(matches!(SchemeObj::read(&mem, car(&mem, old)), Number(2)));
+    }
+
+    #[test]
+    fn live_addresses() {
+        use crate::data::SchemeObj::*;
+
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // (1 . 1), where both halves are the same number.
+        let one = mem.alloc_obj(&Number(1));
+        let pair = crate::data::cons(&mut mem, one, one);
+        mem.alloc_obj(&Number(2));
+        let nil = mem.nil();
+
+        mem.get_roots = Box::new(move || vec![pair, pair]);
+        let before = mem.to_bytes();
+
+        let mut expected = vec![one, pair, nil];
+        expected.sort_unstable();
+
+        assert_eq!(mem.live_addresses(), expected);
+        assert_eq!(mem.to_bytes(), before);
+    }
+
+    #[test]
+    fn retained_bytes() {
+        use crate::data::{cons, SchemeObj::*};
+        use crate::list::alloc_list;
+
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        // Two lists, (1 2 3) and (4 2 3), sharing the tail (2 3).
+        let shared = alloc_list(&mut mem, &[Number(2), Number(3)]);
+        let one = mem.alloc_obj(&Number(1));
+        let four = mem.alloc_obj(&Number(4));
+        let a = cons(&mut mem, one, shared);
+        let b = cons(&mut mem, four, shared);
+
+        mem.get_roots = Box::new(move || vec![a, b]);
+
+        let header_size = Header::new(0, 0, false).size();
+        let block = |obj: SchemeObj| header_size + obj.size();
+        let pair = || Pair {
+            car: Box::new(Nil),
+            cdr: Box::new(Nil),
+        };
+        let expected = block(pair()) + block(Number(1));
+
+        assert_eq!(mem.retained_bytes(a), expected);
+        assert_eq!(mem.retained_bytes(b), expected);
+
+        // Without `b`, `a` retains the shared tail as well (but not the
+        // shared `Nil`, which is always a root).
+        mem.get_roots = Box::new(move || vec![a]);
+        let tail = 2 * block(pair()) + block(Number(2)) + block(Number(3));
+        assert_eq!(mem.retained_bytes(a), expected + tail);
+    }
+
+    #[test]
+    #[cfg(feature = "refcount-header")]
+    fn refcounted_object_survives() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let addr = mem.alloc_bytes(8, false);
+        let header_size = Header::new(0, 0, false).size();
+
+        mem.incref(addr);
+        mem.incref(addr);
+        mem.collect();
+        assert!(Header::read(&mem, addr - header_size).allocd);
+
+        mem.decref(addr);
+        mem.collect();
+        assert!(Header::read(&mem, addr - header_size).allocd);
+
+        // Dropping the last reference frees the block without a
+        // collection, coalescing it with the free space after it.
+        mem.decref(addr);
+        assert_eq!(mem.live_bytes(), 0);
+        assert_eq!(mem.block_addrs(), vec![0]);
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "refcount-header")]
+    fn decref_clears_weak_refs_and_generations() {
+        use crate::data::{cons, set_car, weak_box, weak_deref, SchemeObj::*};
+
+        let mut mem = Heap::new_generational(256, 256, Box::new(Vec::new));
+
+        // An old pair, made to point at a young number, is remembered.
+        let nil = mem.nil();
+        let old = cons(&mut mem, nil, nil);
+        mem.get_roots = Box::new(move || vec![old]);
+        mem.collect();
+        let young = mem.alloc_obj(&Number(1));
+        set_car(&mut mem, old, young);
+        let weak = weak_box(&mut mem, young);
+
+        mem.incref(young);
+        mem.incref(old);
+        mem.decref(young);
+        mem.decref(old);
+
+        assert_eq!(weak_deref(&mem, weak), None);
+        let gens = mem.generations.as_ref().unwrap();
+        assert_eq!(gens.young.len(), 1);
+        assert_eq!(gens.young_bytes, Box::new(Nil).size());
+        assert!(gens.remembered.is_empty());
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "refcount-header")]
+    fn compact_keeps_refcounts() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        mem.set_large_object_threshold(64);
+        let header_size = Header::new(0, 0, false).size();
+        let refcount = |mem: &Heap, addr: usize| Header::read(mem, addr - header_size).refcount;
+
+        mem.alloc_bytes(8, false);
+        let small = mem.alloc_bytes(8, false);
+        let large = mem.alloc_bytes(100, false);
+        mem.incref(small);
+        mem.incref(large);
+
+        // The small object moves (taking its count with it), while the
+        // large one stays put.
+        let forwarding = mem.compact();
+        mem.verify().unwrap();
+        let small = forwarding[&small];
+        assert!(!forwarding.contains_key(&large));
+        assert_eq!(refcount(&mem, small), 1);
+        assert_eq!(refcount(&mem, large), 1);
+
+        mem.collect();
+        assert_eq!(mem.live_bytes(), 108);
+
+        mem.decref(small);
+        mem.decref(large);
+        assert_eq!(mem.live_bytes(), 0);
+    }
+
+    #[test]
+    fn collect_below_threshold() {
+        let collections = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&collections);
+
+        let mut mem = Heap::new(
+            256,
+            Box::new(move || {
+                counter.set(counter.get() + 1);
+                vec![]
+            }),
+        );
+        mem.set_gc_threshold(0.5);
+
+        let n = 12;
+        let block_size = Header::new(0, 0, false).size() + n;
+
+        // Allocate until we're just above the threshold: no collection
+        // should have happened yet.
+        while mem.free_bytes() - block_size >= 128 {
+            mem.alloc_bytes(n, true);
+        }
+        assert_eq!(collections.get(), 0);
+
+        // The next allocation drops below the threshold, and the one
+        // after it triggers a collection.
+        mem.alloc_bytes(n, true);
+        mem.alloc_bytes(n, true);
+        assert_eq!(collections.get(), 1);
+    }
+
+    #[test]
+    fn collect_with_hysteresis() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+        mem.set_gc_threshold(0.9);
+
+        // Everything stays live, so no collection can rise above the
+        // threshold, but collections shouldn't run on every allocation.
+        let allocations = 20;
+        for n in 0..allocations {
+            let addr = mem.alloc_obj(&SchemeObj::Number(n));
+            mem.push_temp_root(addr);
+        }
+
+        let collections = mem.gc_stats().collections;
+        assert!(0 < collections && collections < allocations as usize / 2);
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn alloc_nested_obj_under_pressure() {
+        use crate::data::SchemeObj::*;
+
+        let list = Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Pair {
+                car: Box::new(Number(2)),
+                cdr: Box::new(Nil),
+            }),
+        };
+
+        // Every allocation collects, including those of the list's
+        // children while the list itself is being written.
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+        mem.set_gc_threshold(1.0);
+
+        let addr = mem.alloc_obj(&list);
+        assert_eq!(SchemeObj::read(&mem, addr), list);
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn gc_stats() {
+        let mut mem = Heap::new(128, Box::new(Vec::new));
+
+        let n = 12;
+        mem.alloc_bytes(n, false);
+        mem.alloc_bytes(n, false);
+
+        mem.collect();
+        mem.collect();
+
+        let header_size = Header::new(0, 0, false).size();
+        let stats = mem.gc_stats();
+        assert_eq!(stats.collections, 2);
+        // Both blocks are reclaimed by the first collection (along with
+        // the headers of the blocks that get coalesced away), and
+        // nothing is reclaimed by the second.
+        assert_eq!(stats.bytes_reclaimed, 2 * n + 2 * header_size);
+
+        mem.reset_gc_stats();
+        assert_eq!(mem.gc_stats(), &GcStats::default());
+    }
+
+    #[test]
+    fn alloc_size_histogram() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        for _ in 0..3 {
+            mem.alloc_bytes(12, false);
+        }
+        for _ in 0..2 {
+            mem.alloc_bytes(20, false);
+        }
+
+        let histogram = mem.alloc_size_histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram[&12], 3);
+        assert_eq!(histogram[&20], 2);
+    }
+
+    #[test]
+    fn collect_incremental() {
+        use crate::data::SchemeObj::*;
+
+        let build = || {
+            let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+            // ((1 . 2) . 3), only part of which will be kept alive.
+            let inner = Box::new(Pair {
+                car: Box::new(Number(1)),
+                cdr: Box::new(Number(2)),
+            });
+            let outer = Pair {
+                car: inner,
+                cdr: Box::new(Number(3)),
+            };
+
+            let root = mem.alloc_obj(&outer);
+            mem.alloc_obj(&Number(4));
+
+            mem.get_roots = Box::new(move || vec![root]);
+            mem
+        };
+
+        let mut blocking = build();
+        blocking.collect();
+
+        let mut incremental = build();
+        assert!(!incremental.collection_in_progress());
+
+        let mut steps = 1;
+        while incremental.collect_incremental(1) == CollectProgress::More {
+            assert!(incremental.collection_in_progress());
+            steps += 1;
+        }
+        assert!(!incremental.collection_in_progress());
+
+        assert!(steps > 1);
+        assert_eq!(incremental.space, blocking.space);
+        assert_eq!(incremental.gc_stats().collections, 1);
+    }
+
+    #[test]
+    fn collect_incremental_write_barrier() {
+        use crate::data::{car, cons, set_car};
+        use crate::list::alloc_list;
+
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let other = alloc_list(&mut mem, &[SchemeObj::Number(1), SchemeObj::Number(2)]);
+        let one = car(&mem, other);
+        let nil = mem.nil();
+        let root = cons(&mut mem, one, nil);
+        let x = mem.alloc_obj(&SchemeObj::Number(42));
+
+        // The most recently pushed root is scanned first, so a single
+        // step blackens `root` while leaving the cycle unfinished.
+        mem.push_temp_root(other);
+        mem.push_temp_root(root);
+        assert_eq!(mem.collect_incremental(1), CollectProgress::More);
+
+        set_car(&mut mem, root, x);
+        while mem.collect_incremental(1) == CollectProgress::More {}
+
+        mem.alloc_obj(&SchemeObj::Number(7));
+        assert_eq!(
+            SchemeObj::read(&mem, car(&mem, root)),
+            SchemeObj::Number(42)
+        );
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn alloc_grows_heap() {
+        let mut mem = Heap::new_growable(64, 1024, Box::new(Vec::new));
+
+        let first = mem.alloc_bytes(30, true);
+        mem.get_roots = Box::new(move || vec![first]);
+
+        let second = mem.alloc_bytes(100, true);
+
+        assert_eq!(mem.space.len(), 256);
+        mem.verify().unwrap();
+
+        let header_size = Header::new(0, 0, false).size();
+        let first_header = Header::read(&mem, first - header_size);
+        let mut last_header = Header::read(&mem, first_header.next);
+        while last_header.next != 0 {
+            last_header = Header::read(&mem, last_header.next);
+        }
+
+        assert!(first_header.allocd);
+        assert!(Header::read(&mem, second - header_size).allocd);
+        assert!(!last_header.allocd);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut mem = Heap::new_growable(64, 1024, Box::new(Vec::new));
+
+        let kept = mem.alloc_obj(&SchemeObj::Number(7));
+        mem.push_temp_root(kept);
+        let garbage = mem.alloc_bytes(300, false);
+        assert_eq!(mem.capacity(), 512);
+
+        mem.shrink_to_fit();
+        assert_eq!(mem.capacity(), garbage + 300);
+
+        // Now the last block is allocated, so there's nothing to release.
+        mem.shrink_to_fit();
+        assert_eq!(mem.capacity(), garbage + 300);
+
+        mem.collect();
+        mem.shrink_to_fit();
+        assert!(mem.capacity() < 64);
+        assert_eq!(SchemeObj::read(&mem, kept), SchemeObj::Number(7));
+        mem.verify().unwrap();
+
+        // The heap grows again on demand.
+        mem.alloc_bytes(100, false);
+        mem.verify().unwrap();
+
+        let mut mem = Heap::new(64, Box::new(Vec::new));
+        mem.shrink_to_fit();
+        assert_eq!(mem.capacity(), Header::new(0, 0, false).size());
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn capacity() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        assert_eq!(mem.capacity(), 256);
+
+        let header_size = Header::new(0, 0, false).size();
+        assert_eq!(mem.free_bytes(), 256 - header_size);
+
+        mem.alloc_bytes(16, false);
+        assert_eq!(mem.capacity(), 256);
+        assert_eq!(mem.free_bytes(), 256 - 2 * header_size - 16);
+    }
+
+    #[test]
+    fn verify() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        assert_eq!(mem.verify(), Ok(()));
+
+        let first = mem.alloc_bytes(16, false);
+        let second = mem.alloc_bytes(32, false);
+        assert_eq!(mem.verify(), Ok(()));
+
+        mem.get_roots = Box::new(move || vec![second]);
+        mem.collect();
+        assert_eq!(mem.verify(), Ok(()));
+
+        let header_size = Header::new(0, 0, false).size();
+        let mut header = Header::read(&mem, second - header_size);
+        header.next += 1;
+        header.write(&mut mem, second - header_size);
+        assert_eq!(
+            mem.verify(),
+            Err(HeapError::BadNext {
+                addr: second - header_size,
+                next: second + 33,
+                expected: second + 32,
+            })
+        );
+
+        mem.write(first - 1, 0);
+        assert_eq!(mem.verify(), Err(HeapError::CorruptHeader(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "heap size 4 too small")]
+    fn new_too_small() {
+        Heap::new(4, Box::new(Vec::new));
+    }
+
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn new_too_large() {
+        Heap::new(LARGE_BASE + 1, Box::new(Vec::new));
+    }
+
+    #[test]
     #[should_panic]
+    fn alloc_grows_past_max() {
+        let mut mem = Heap::new_growable(64, 128, Box::new(Vec::new));
+
+        mem.alloc_bytes(200, true);
+    }
+
+    #[test]
+    fn write_read_bytes() {
+        let mut mem = Heap::new(64, Box::new(Vec::new));
+
+        let data = [3, 1, 4, 1, 5, 9, 2, 6, 5, 3];
+        let addr = mem.alloc_bytes(data.len(), false);
+
+        mem.write_bytes(addr, &data);
+
+        assert_eq!(mem.read_bytes(addr, data.len()), data);
+        assert_eq!(mem.read(addr + 5), 9);
+    }
+
+    #[test]
+    fn shared_nil() {
+        use crate::data::{unbox, SchemeObj::*};
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let nil = mem.nil();
+        let free = mem.free_bytes();
+
+        // Each of these lists ends in a boxed `Nil` that should point at
+        // the shared instance, without allocating a new one.
+        let list = Pair {
+            car: Box::new(Nil),
+            cdr: Box::new(Nil),
+        };
+        let a = mem.alloc_obj(&list);
+        let b = mem.alloc_obj(&list);
+
+        assert_eq!(unbox(&mem, a + 1), nil);
+        assert_eq!(unbox(&mem, a + 1 + BOX_SIZE), nil);
+        assert_eq!(unbox(&mem, b + 1 + BOX_SIZE), nil);
+
+        let header_size = Header::new(0, 0, false).size();
+        assert_eq!(mem.free_bytes(), free - 2 * (header_size + list.size()));
+
+        // The shared `Nil` survives collection, even though it isn't
+        // reachable from any root.
+        mem.collect();
+        assert_eq!(mem.nil(), nil);
+        assert!(matches!(SchemeObj::read(&mem, nil), Nil));
+        assert_eq!(mem.free_bytes(), free);
+    }
+
+    #[test]
+    fn hex_dump() {
+        let mut mem = Heap::new(96, Box::new(Vec::new));
+
+        // Keep the text on a single line of the dump.
+        let addr = mem.alloc_bytes(20, false);
+        mem.write_bytes(addr + (16 - addr % 16) % 16, b"hi!\n");
+
+        let header_size = Header::new(0, 0, false).size();
+        let dump = mem.hex_dump(0, 96);
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("00000000  "));
+        assert!(lines[0].contains("headers: 0x0"));
+        assert!(lines[1].starts_with("00000010  "));
+        assert!(dump.contains("hi!."));
+
+        let second = format!("{:#x}", header_size + 20);
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("headers: ") && line.ends_with(&second)));
+    }
+
+    #[test]
+    fn dump_headers() {
+        let mut mem = Heap::new(64, Box::new(Vec::new));
+
+        mem.alloc_bytes(4, false);
+
+        let header_size = Header::new(0, 0, false).size();
+        let second = header_size + 4;
+
+        assert_eq!(
+            mem.dump_headers(),
+            format!(
+                "(0, {}, 4, true, false)\n({}, 0, {}, false, false)\n",
+                second,
+                second,
+                64 - 4 - 2 * header_size
+            )
+        );
+    }
+
+    #[test]
+    fn intern_constant() {
+        use crate::data::SchemeObj::*;
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let seven = mem.intern_constant(&Number(7));
+        let truth = mem.intern_constant(&Bool(true));
+        let free = mem.free_bytes();
+
+        assert_eq!(mem.intern_constant(&Number(7)), seven);
+        assert_eq!(mem.intern_constant(&Bool(true)), truth);
+        assert_eq!(mem.free_bytes(), free);
+
+        assert_ne!(mem.intern_constant(&Bool(false)), truth);
+        assert_ne!(mem.intern_constant(&Symbol(7)), seven);
+
+        // Pooled constants survive collection.
+        let free = mem.free_bytes();
+        mem.collect();
+        assert_eq!(mem.free_bytes(), free);
+        assert_eq!(mem.intern_constant(&Number(7)), seven);
+        assert!(matches!(SchemeObj::read(&mem, seven), Number(7)));
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        use crate::data::{scheme_equal, SchemeObj::*};
+
+        let list = || Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Pair {
+                car: Box::new(Symbol(2)),
+                cdr: Box::new(Nil),
+            }),
+        };
+
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+        let addr = mem.alloc_obj(&list());
+        let image = mem.to_bytes();
+
+        let mut restored = Heap::from_bytes(image, Box::new(move || vec![addr]));
+        assert_eq!(restored.space, mem.space);
+
+        let copy = restored.alloc_obj(&list());
+        assert!(scheme_equal(&restored, addr, copy));
+
+        // The restored heap's block chain is intact enough to collect.
+        restored.collect();
+        let copy = restored.alloc_obj(&list());
+        assert!(scheme_equal(&restored, addr, copy));
+    }
+
+    #[test]
+    #[cfg(feature = "pointer-width-2")]
+    fn two_byte_pointers() {
+        use crate::data::{weak_box, weak_deref, SchemeObj::*};
+
+        let list = || Pair {
+            car: Box::new(Number(-1)),
+            cdr: Box::new(Pair {
+                car: Box::new(Bytevector(vec![1, 2, 3])),
+                cdr: Box::new(Nil),
+            }),
+        };
+
+        assert_eq!(LARGE_BASE, 1 << 15);
+        assert_eq!(
+            Pair {
+                car: Box::new(Nil),
+                cdr: Box::new(Nil)
+            }
+            .size(),
+            7
+        );
+
+        // Compact headers leave only 14 bits for a block's size.
+        let size = if cfg!(feature = "compact-header") {
+            1 << 14
+        } else {
+            LARGE_BASE
+        };
+        let mut mem = Heap::new(size, Box::new(Vec::new));
+
+        // Push the list far enough in that its pointers need both bytes.
+        for i in 0..500 {
+            mem.alloc_obj(&Number(i));
+        }
+        let addr = mem.alloc_obj(&list());
+        let weak = weak_box(&mut mem, addr);
+        assert!(addr > 0xFF);
+        assert_eq!(SchemeObj::read(&mem, addr), list());
+
+        mem.get_roots = Box::new(move || vec![addr, weak]);
+        let forwarding = mem.compact();
+        mem.verify().unwrap();
+
+        let (addr, weak) = (forwarding[&addr], forwarding[&weak]);
+        assert_eq!(SchemeObj::read(&mem, addr), list());
+        assert_eq!(weak_deref(&mem, weak), Some(addr));
+
+        let restored = Heap::from_bytes(mem.to_bytes(), Box::new(Vec::new));
+        assert_eq!(SchemeObj::read(&restored, addr), list());
+    }
+
+    #[test]
+    fn compact() {
+        use crate::data::{cdr, scheme_equal, SchemeObj::*};
+
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let header_size = Header::new(0, 0, false).size();
+        let list = || Pair {
+            car: Box::new(Number(2)),
+            cdr: Box::new(Pair {
+                car: Box::new(Symbol(3)),
+                cdr: Box::new(Nil),
+            }),
+        };
+
+        let nil = mem.nil();
+        let garbage = mem.alloc_obj(&Number(1));
+        let root = mem.alloc_obj(&list());
+        mem.alloc_obj(&Bool(false));
+
+        mem.get_roots = Box::new(move || vec![root]);
+        let forwarding = mem.compact();
+        mem.verify().unwrap();
+
+        // The list's four blocks and the shared `Nil` are all that
+        // survive, and are followed by a single free block.
+        let blocks = mem.block_addrs();
+        assert_eq!(blocks.len(), 6);
+        assert!(blocks[..5]
+            .iter()
+            .all(|&addr| Header::read(&mem, addr).allocd));
+
+        let live_bytes = 5 * header_size + 3 * Nil.size() + 2 * list().size();
+        let free_header = Header::read(&mem, blocks[5]);
+        assert_eq!(blocks[5], live_bytes);
+        assert_eq!(
+            free_header,
+            Header::new(0, 1024 - live_bytes - header_size, false)
+        );
+
+        assert_eq!(forwarding.len(), 5);
+        assert!(!forwarding.contains_key(&garbage));
+        assert_eq!(forwarding[&nil], nil);
+        assert_eq!(mem.nil(), nil);
+
+        // The list slides down into the garbage's place, and its
+        // internal pointers are updated.
+        let moved = forwarding[&root];
+        assert_eq!(moved, garbage);
+        assert_eq!(cdr(&mem, cdr(&mem, moved)), nil);
+
+        let expected = mem.alloc_obj(&list());
+        assert!(scheme_equal(&mem, moved, expected));
+    }
+
+    #[test]
+    fn collect_compact_with_map() {
+        use crate::data::SchemeObj::*;
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        mem.alloc_obj(&Number(1));
+        let held = mem.alloc_obj(&Symbol(7));
+
+        mem.get_roots = Box::new(move || vec![held]);
+        let forwarding = mem.collect_compact_with_map();
+        mem.verify().unwrap();
+
+        let moved = forwarding[&held];
+        assert_ne!(moved, held);
+        assert_eq!(SchemeObj::read(&mem, moved), Symbol(7));
+
+        mem.get_roots = Box::new(move || vec![moved]);
+        mem.collect();
+        assert_eq!(SchemeObj::read(&mem, moved), Symbol(7));
+    }
+
+    #[test]
+    fn weak_box_does_not_pin() {
+        use crate::data::{weak_box, weak_deref, SchemeObj::*};
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let kept = mem.alloc_obj(&Number(1));
+        let dropped = mem.alloc_obj(&Number(2));
+        let kept_ref = weak_box(&mut mem, kept);
+        let dropped_ref = weak_box(&mut mem, dropped);
+
+        mem.get_roots = Box::new(move || vec![kept, kept_ref, dropped_ref]);
+        mem.collect();
+
+        let header_size = Header::new(0, 0, false).size();
+        assert!(!Header::read(&mem, dropped - header_size).allocd);
+        assert_eq!(weak_deref(&mem, dropped_ref), None);
+        assert_eq!(weak_deref(&mem, kept_ref), Some(kept));
+    }
+
+    #[test]
+    fn compact_forwards_weak_box() {
+        use crate::data::{weak_box, weak_deref, SchemeObj::*};
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        mem.alloc_obj(&Number(0));
+        let target = mem.alloc_obj(&Number(1));
+        let weak = weak_box(&mut mem, target);
+
+        mem.get_roots = Box::new(move || vec![target, weak]);
+        let forwarding = mem.compact();
+
+        let weak = forwarding[&weak];
+        assert_eq!(weak_deref(&mem, weak), Some(forwarding[&target]));
+        assert!(matches!(
+            SchemeObj::read(&mem, forwarding[&target]),
+            Number(1)
+        ));
+    }
+
+    #[test]
+    fn finalizer_runs_when_collected() {
+        let finalized = Rc::new(Cell::new(false));
+        let flag = Rc::clone(&finalized);
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+
+        let addr = mem.alloc_bytes(8, false);
+        mem.register_finalizer(addr, Box::new(move || flag.set(true)));
+
+        // While the object is reachable, its finalizer stays registered.
+        mem.get_roots = Box::new(move || vec![addr]);
+        mem.collect();
+        assert!(!finalized.get());
+        assert_eq!(mem.finalizers.len(), 1);
+
+        mem.get_roots = Box::new(Vec::new);
+        mem.collect();
+        assert!(finalized.get());
+        assert!(mem.finalizers.is_empty());
+    }
+
+    #[test]
+    fn best_fit_avoids_splitting() {
+        let header_size = Header::new(0, 0, false).size();
+        let size = 4 * header_size + 40 + 8 + 16 + 8;
+
+        let largest_free_after = |strategy| {
+            let mut mem = Heap::new_with_strategy(size, strategy, Box::new(Vec::new));
+
+            mem.alloc_bytes(40, false);
+            let b = mem.alloc_bytes(8, false);
+            mem.alloc_bytes(16, false);
+            let d = mem.alloc_bytes(8, false);
+
+            // Leaves free blocks of 40 and 16 bytes, separated by `b`.
+            mem.get_roots = Box::new(move || vec![b, d]);
+            mem.collect();
+
+            mem.alloc_bytes(16, false);
+
+            mem.block_addrs()
+                .into_iter()
+                .map(|addr| Header::read(&mem, addr))
+                .filter(|header| !header.allocd)
+                .map(|header| header.size)
+                .max()
+                .unwrap_or(0)
+        };
+
+        assert_eq!(largest_free_after(AllocStrategy::BestFit), 40);
+        assert!(largest_free_after(AllocStrategy::FirstFit) < 40);
+    }
+
+    #[test]
+    fn live_bytes() {
+        let scan = |mem: &Heap| -> usize {
+            mem.block_addrs()
+                .into_iter()
+                .map(|addr| Header::read(mem, addr))
+                .filter(|header| header.allocd)
+                .map(|header| header.size)
+                .sum()
+        };
+
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        assert_eq!(mem.live_bytes(), 0);
+
+        let kept = mem.alloc_bytes(12, false);
+        mem.alloc_bytes(30, false);
+        mem.alloc_bytes(7, false);
+        assert_eq!(mem.live_bytes(), 49);
+        assert_eq!(mem.live_bytes(), scan(&mem));
+
+        mem.get_roots = Box::new(move || vec![kept]);
+        mem.collect();
+        assert_eq!(mem.live_bytes(), 12);
+        assert_eq!(mem.live_bytes(), scan(&mem));
+
+        mem.alloc_bytes(20, false);
+        assert_eq!(mem.live_bytes(), scan(&mem));
+
+        let restored = Heap::from_bytes(mem.to_bytes(), Box::new(Vec::new));
+        assert_eq!(restored.live_bytes(), mem.live_bytes());
+
+        mem.compact();
+        assert_eq!(mem.live_bytes(), 12);
+        assert_eq!(mem.live_bytes(), scan(&mem));
+    }
+
+    #[test]
+    fn large_objects() {
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        mem.set_large_object_threshold(64);
+
+        let mut small = vec![];
+        let mut large = vec![];
+        for i in 0..5 {
+            small.push(mem.alloc_bytes(12, false));
+
+            let addr = mem.alloc_bytes(200, false);
+            mem.write_bytes(addr, &[i as u8; 200]);
+            large.push(addr);
+        }
+
+        assert!(large.iter().all(|&addr| addr >= LARGE_BASE));
+        assert!(small.iter().all(|&addr| addr < LARGE_BASE));
+
+        // Every block in the main region is small, and the small objects
+        // are packed together at its start.
+        let header_size = Header::new(0, 0, false).size();
+        let blocks = mem.block_addrs();
+        assert_eq!(blocks.len(), small.len() + 1);
+        for (i, &addr) in small.iter().enumerate() {
+            assert_eq!(addr, i * (header_size + 12) + header_size);
+        }
+
+        // Once the small objects die, the main region coalesces back into
+        // a single free block, while the rooted large objects survive.
+        let roots = large.clone();
+        mem.get_roots = Box::new(move || roots.clone());
+        mem.collect();
+
+        assert_eq!(mem.block_addrs(), vec![0]);
+        assert_eq!(mem.live_bytes(), 5 * 200);
+        for (i, &addr) in large.iter().enumerate() {
+            assert_eq!(mem.read_bytes(addr, 200), vec![i as u8; 200]);
+        }
+
+        // Large objects aren't moved by compaction, and a dead large
+        // object's block is reused.
+        let forwarding = mem.compact();
+        assert!(large.iter().all(|addr| !forwarding.contains_key(addr)));
+
+        mem.get_roots = Box::new(Vec::new);
+        mem.collect();
+        assert_eq!(mem.alloc_bytes(150, false), large[0]);
+    }
+
+    #[test]
+    fn compact_keeps_large_roots() {
+        use crate::data::SchemeObj::*;
+
+        let half = SchemeObj::rational(1, 2).unwrap();
+        let mut mem = Heap::new(256, Box::new(Vec::new));
+        mem.set_large_object_threshold(half.size());
+
+        // Numbers are small enough to stay in the main region (and so
+        // move), while rationals and the bytevector are large.
+        let bytes = Bytevector(vec![7; 100]);
+        mem.alloc_obj(&Number(0));
+        let small = mem.alloc_obj(&Number(1));
+        let large = mem.alloc_obj(&bytes);
+        let constant = mem.intern_constant(&half);
+        assert!(small < LARGE_BASE);
+        assert!(large >= LARGE_BASE && constant >= LARGE_BASE);
+
+        mem.push_temp_root(large);
+        mem.push_temp_root(small);
+        let forwarding = mem.compact();
+        mem.verify().unwrap();
+
+        let small = forwarding[&small];
+        assert_eq!(mem.pop_temp_root(), Some(small));
+        assert_eq!(mem.pop_temp_root(), Some(large));
+        assert_eq!(mem.intern_constant(&half), constant);
+        assert_eq!(SchemeObj::read(&mem, small), Number(1));
+        assert_eq!(SchemeObj::read(&mem, large), bytes);
+        assert_eq!(SchemeObj::read(&mem, constant), half);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of memory")]
     fn alloc_too_big() {
-        let mut mem = Heap::new(10, Box::new(|| vec![]));
+        let mut mem = Heap::new(64, Box::new(|| vec![]));
 
         mem.alloc_bytes(123, false);
     }
@@ -1,3 +1,6 @@
-mod data;
-mod heap;
-mod memory;
+pub mod data;
+pub mod heap;
+pub mod list;
+pub mod memory;
+pub mod prims;
+pub mod print;
@@ -0,0 +1,394 @@
+use crate::data::{car, cdr, cons, scheme_equal, SchemeObj, Tag};
+use crate::memory::Mem;
+
+/// A cursor over the pairs of a heap list, yielding the address of each
+/// pair's car as it follows the chain of cdrs. Once the iterator is
+/// exhausted, `is_proper` reports whether the chain ended in `Nil`.
+///
+/// Cyclic lists are detected (by trailing a second cursor that moves at
+/// half speed, which the first must eventually catch) and treated as
+/// improper, so iteration always terminates.
+pub struct ListIter<'a, M: Mem> {
+    mem: &'a M,
+    addr: usize,
+    slow: usize,
+    move_slow: bool,
+    proper: Option<bool>,
+}
+
+impl<'a, M: Mem> ListIter<'a, M> {
+    pub fn new(mem: &'a M, list: usize) -> ListIter<'a, M> {
+        ListIter {
+            mem,
+            addr: list,
+            slow: list,
+            move_slow: false,
+            proper: None,
+        }
+    }
+
+    /// Returns `Some(true)` if the list ended in `Nil`, `Some(false)` if
+    /// it ended in some other (non-pair) object or turned out to be
+    /// cyclic, or `None` if iteration hasn't yet reached the end of the
+    /// list.
+    pub fn is_proper(&self) -> Option<bool> {
+        self.proper
+    }
+}
+
+impl<'a, M: Mem> Iterator for ListIter<'a, M> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.proper.is_some() {
+            return None;
+        }
+
+        match Tag::from(self.mem.read(self.addr)) {
+            Tag::Pair => {
+                let elem = car(self.mem, self.addr);
+                self.addr = cdr(self.mem, self.addr);
+
+                if self.move_slow {
+                    self.slow = cdr(self.mem, self.slow);
+                }
+                self.move_slow = !self.move_slow;
+                if self.addr == self.slow {
+                    self.proper = Some(false);
+                }
+
+                Some(elem)
+            }
+            tag => {
+                self.proper = Some(tag == Tag::Nil);
+                None
+            }
+        }
+    }
+}
+
+/// Allocates each of `elems`, and returns the address of a proper list
+/// holding them (in order).
+pub fn alloc_list<M: Mem>(mem: &mut M, elems: &[SchemeObj]) -> usize {
+    let mut addrs = vec![];
+    for elem in elems {
+        let addr = mem.alloc_obj(elem);
+        mem.push_temp_root(addr);
+        addrs.push(addr);
+    }
+
+    let list = alloc_list_addrs(mem, &addrs);
+    pop_temp_roots(mem, addrs.len());
+
+    list
+}
+
+/// Returns the address of a proper list of the (already allocated)
+/// objects at `addrs`.
+pub fn alloc_list_addrs<M: Mem>(mem: &mut M, addrs: &[usize]) -> usize {
+    rebuild_onto_nil(mem, addrs)
+}
+
+/// Returns a list of the elements of `list` that aren't `equal?` to the
+/// object at `key`, as per SRFI-1's `delete`.
+///
+/// # Notes
+///
+/// The result shares its elements (but not its pairs) with `list`, and
+/// shares its longest tail without any deleted elements with `list`.
+pub fn delete<M: Mem>(mem: &mut M, key: usize, list: usize) -> usize {
+    delete_by(mem, key, list, scheme_equal)
+}
+
+/// Like `delete`, but compares elements to `key` with `eq` rather than
+/// `equal?`. `eq` is called with the key's address first.
+pub fn delete_by<M, F>(mem: &mut M, key: usize, list: usize, eq: F) -> usize
+where
+    M: Mem,
+    F: Fn(&M, usize, usize) -> bool,
+{
+    let elems = elements(mem, list);
+
+    // Everything after the last deleted element can be shared as-is.
+    let last = match elems.iter().rposition(|&elem| eq(mem, key, elem)) {
+        Some(last) => last,
+        None => return list,
+    };
+
+    let kept: Vec<usize> = elems[..last]
+        .iter()
+        .copied()
+        .filter(|&elem| !eq(mem, key, elem))
+        .collect();
+    let tail = nth_tail(mem, list, last + 1);
+
+    rebuild(mem, &kept, tail)
+}
+
+/// Returns a list of the elements of `list` with all but the first of
+/// any `equal?` elements removed, as per SRFI-1's `delete-duplicates`.
+pub fn delete_duplicates<M: Mem>(mem: &mut M, list: usize) -> usize {
+    delete_duplicates_by(mem, list, scheme_equal)
+}
+
+/// Like `delete_duplicates`, but compares elements with `eq` rather than
+/// `equal?`. `eq` is called with the earlier element's address first.
+pub fn delete_duplicates_by<M, F>(mem: &mut M, list: usize, eq: F) -> usize
+where
+    M: Mem,
+    F: Fn(&M, usize, usize) -> bool,
+{
+    let mut kept: Vec<usize> = vec![];
+
+    for elem in elements(mem, list) {
+        if !kept.iter().any(|&k| eq(mem, k, elem)) {
+            kept.push(elem);
+        }
+    }
+
+    rebuild_onto_nil(mem, &kept)
+}
+
+/// Returns the addresses of the elements of the proper list `list`.
+///
+/// # Panics
+///
+/// Panics if `list` isn't a proper list.
+fn elements<M: Mem>(mem: &M, list: usize) -> Vec<usize> {
+    let mut iter = ListIter::new(mem, list);
+    let elems: Vec<usize> = iter.by_ref().collect();
+
+    match iter.is_proper() {
+        Some(true) => elems,
+        _ => panic!("Expected a proper list at {}", list),
+    }
+}
+
+/// Returns the tail of `list` that remains after dropping `n` pairs.
+///
+/// # Panics
+///
+/// Panics if `list` has fewer than `n` pairs.
+pub fn nth_tail<M: Mem>(mem: &M, list: usize, n: usize) -> usize {
+    (0..n).fold(list, |addr, _| cdr(mem, addr))
+}
+
+/// Allocates a list of `elems`, followed by the existing list `tail`.
+///
+/// # Notes
+///
+/// Each `cons` may trigger a collection, so the elements, along with the
+/// part of the list built so far, are kept as temporary roots until the
+/// list is complete.
+fn rebuild<M: Mem>(mem: &mut M, elems: &[usize], tail: usize) -> usize {
+    for &elem in elems {
+        mem.push_temp_root(elem);
+    }
+
+    let mut list = tail;
+    for &elem in elems.iter().rev() {
+        mem.push_temp_root(list);
+        let pair = cons(mem, elem, list);
+        mem.pop_temp_root();
+
+        list = pair;
+    }
+
+    pop_temp_roots(mem, elems.len());
+
+    list
+}
+
+/// Allocates a proper list of `elems`. Like `rebuild`, this keeps the
+/// elements rooted, including while `Nil` is (possibly) allocated.
+fn rebuild_onto_nil<M: Mem>(mem: &mut M, elems: &[usize]) -> usize {
+    for &elem in elems {
+        mem.push_temp_root(elem);
+    }
+    let nil = mem.nil();
+    pop_temp_roots(mem, elems.len());
+
+    rebuild(mem, elems, nil)
+}
+
+/// Pops the `n` most recently pushed temporary roots.
+fn pop_temp_roots<M: Mem>(mem: &mut M, n: usize) {
+    for _ in 0..n {
+        mem.pop_temp_root();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{
+        set_cdr,
+        SchemeObj::{self, *},
+    };
+    use crate::heap::Heap;
+    use crate::memory::MemRead;
+
+    fn list(nums: &[isize]) -> SchemeObj {
+        nums.iter().rev().fold(Nil, |rest, &n| Pair {
+            car: Box::new(Number(n)),
+            cdr: Box::new(rest),
+        })
+    }
+
+    #[test]
+    fn alloc_list_of_numbers() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let result = alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+
+        let elems: Vec<SchemeObj> = elements(&mem, result)
+            .into_iter()
+            .map(|addr| SchemeObj::read(&mem, addr))
+            .collect();
+        assert_eq!(elems, vec![Number(1), Number(2), Number(3)]);
+        assert_eq!(SchemeObj::read(&mem, result), list(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn alloc_list_survives_collection() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // Every allocation now collects, so the elements (and the list
+        // being built) must be rooted.
+        mem.set_gc_threshold(1.0);
+        let nums: Vec<SchemeObj> = (1..=8).map(Number).collect();
+        let result = alloc_list(&mut mem, &nums);
+        mem.set_gc_threshold(0.0);
+
+        assert_eq!(
+            SchemeObj::read(&mem, result),
+            list(&[1, 2, 3, 4, 5, 6, 7, 8])
+        );
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn iterate_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let src = mem.alloc_obj(&list(&[10, 20, 30]));
+
+        let mut iter = ListIter::new(&mem, src);
+        assert_eq!(iter.is_proper(), None);
+
+        let nums: Vec<isize> = iter
+            .by_ref()
+            .map(|addr| match SchemeObj::read(&mem, addr) {
+                Number(n) => n,
+                obj => panic!("Expected a number, got {:?}", obj),
+            })
+            .collect();
+        assert_eq!(nums, vec![10, 20, 30]);
+        assert_eq!(iter.is_proper(), Some(true));
+    }
+
+    #[test]
+    fn iterate_improper_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let src = mem.alloc_obj(&Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Number(2)),
+        });
+
+        let mut iter = ListIter::new(&mem, src);
+        assert_eq!(iter.by_ref().count(), 1);
+        assert_eq!(iter.is_proper(), Some(false));
+    }
+
+    #[test]
+    fn iterate_cyclic_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        for len in 1..=4 {
+            let src = mem.alloc_obj(&list(&[1, 2, 3, 4][..len]));
+            let last = nth_tail(&mem, src, len - 1);
+            set_cdr(&mut mem, last, src);
+
+            let mut iter = ListIter::new(&mem, src);
+            assert!(iter.by_ref().count() <= 2 * len);
+            assert_eq!(iter.is_proper(), Some(false));
+        }
+    }
+
+    #[test]
+    fn delete_all_equal() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let key = mem.alloc_obj(&Number(2));
+        let src = mem.alloc_obj(&list(&[1, 2, 3, 2]));
+
+        let result = delete(&mut mem, key, src);
+        let expected = mem.alloc_obj(&list(&[1, 3]));
+
+        assert!(scheme_equal(&mem, result, expected));
+    }
+
+    #[test]
+    fn delete_nothing() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let key = mem.alloc_obj(&Number(4));
+        let src = mem.alloc_obj(&list(&[1, 2, 3]));
+
+        assert_eq!(delete(&mut mem, key, src), src);
+    }
+
+    #[test]
+    fn delete_with_comparator() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let key = mem.alloc_obj(&Number(2));
+        let src = mem.alloc_obj(&list(&[1, 2, 3, 4]));
+
+        // Delete everything greater than the key.
+        let result = delete_by(&mut mem, key, src, |mem, key, elem| {
+            match (SchemeObj::read(mem, key), SchemeObj::read(mem, elem)) {
+                (Number(k), Number(e)) => e > k,
+                _ => false,
+            }
+        });
+        let expected = mem.alloc_obj(&list(&[1, 2]));
+
+        assert!(scheme_equal(&mem, result, expected));
+    }
+
+    #[test]
+    fn delete_survives_collection() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let key = mem.alloc_obj(&Number(2));
+        let src = mem.alloc_obj(&list(&[1, 2, 3, 2, 4, 1]));
+        mem.push_temp_root(key);
+        mem.push_temp_root(src);
+
+        // Every allocation now collects, so the kept elements (and the
+        // lists being rebuilt) must be rooted.
+        mem.set_gc_threshold(1.0);
+        let deleted = delete(&mut mem, key, src);
+        mem.push_temp_root(deleted);
+        let deduped = delete_duplicates(&mut mem, src);
+        mem.set_gc_threshold(0.0);
+
+        assert_eq!(SchemeObj::read(&mem, deleted), list(&[1, 3, 4, 1]));
+        assert_eq!(SchemeObj::read(&mem, deduped), list(&[1, 2, 3, 4]));
+        mem.verify().unwrap();
+    }
+
+    #[test]
+    fn delete_duplicate_elements() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let src = mem.alloc_obj(&list(&[1, 2, 1, 3, 2]));
+
+        let result = delete_duplicates(&mut mem, src);
+        let expected = mem.alloc_obj(&list(&[1, 2, 3]));
+
+        assert!(scheme_equal(&mem, result, expected));
+    }
+}
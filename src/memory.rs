@@ -1,7 +1,10 @@
 mod aux;
 mod header;
 
-pub use header::Header;
+pub use aux::{Pointer, POINTER_WIDTH};
+pub use header::{Header, MAGIC};
+
+use crate::data::SchemeObj;
 
 /// Represents a memory store as a "sink of bytes". This entails two
 /// capabilities: writing a byte to a specific location, and reading the
@@ -11,6 +14,51 @@ pub trait Mem {
     /// freshly-allocated bytes.
     fn alloc<T: MemWrite>(&mut self, obj: &T) -> usize;
 
+    /// Allocate space for `obj`, write it to the freshly-allocated
+    /// bytes, and return a pointer to them.
+    fn alloc_obj<T: MemWrite>(&mut self, obj: &T) -> usize
+    where
+        Self: Sized,
+    {
+        let addr = self.alloc(obj);
+        obj.write(self, addr);
+        addr
+    }
+
+    /// Return the address of the empty list, `Nil`.
+    ///
+    /// # Notes
+    ///
+    /// All `Nil`s are identical, so implementations are encouraged to
+    /// allocate a single shared instance and return its address every
+    /// time. The default implementation allocates a fresh `Nil` on each
+    /// call.
+    fn nil(&mut self) -> usize
+    where
+        Self: Sized,
+    {
+        self.alloc_obj(&SchemeObj::Nil)
+    }
+
+    /// Called whenever a pointer to the object at `child_addr` is stored
+    /// into the existing object at `parent_addr` (e.g. by `set_car`), so
+    /// that implementations can keep track of such pointers. The default
+    /// implementation does nothing.
+    fn write_barrier(&mut self, _parent_addr: usize, _child_addr: usize) {}
+
+    /// Keeps the object at `addr` alive across any collections (such as
+    /// those an allocation may trigger) until it's popped again with
+    /// `pop_temp_root`. This lets code that builds a structure from
+    /// several allocations hold onto the parts it's built so far. The
+    /// default implementation does nothing.
+    fn push_temp_root(&mut self, _addr: usize) {}
+
+    /// Pops the most recently pushed temporary root, returning its
+    /// address. The default implementation returns `None`.
+    fn pop_temp_root(&mut self) -> Option<usize> {
+        None
+    }
+
     /// Write the provided byte to the location indicated by `addr`.
     fn write(&mut self, addr: usize, datum: u8);
 
@@ -23,6 +71,29 @@ pub trait Mem {
     /// segmentation fault, and represents a logical error in an
     /// implementation of the `MemRead` trait.
     fn read(&self, addr: usize) -> u8;
+
+    /// Read `len` consecutive bytes, starting at `addr`.
+    ///
+    /// # Notes
+    ///
+    /// The default implementation reads one byte at a time via `read`;
+    /// implementations backed by contiguous storage should override it.
+    fn read_bytes(&self, addr: usize, len: usize) -> Vec<u8> {
+        (addr..addr + len).map(|a| self.read(a)).collect()
+    }
+
+    /// Write each of the bytes in `data` to consecutive locations,
+    /// starting at `addr`.
+    ///
+    /// # Notes
+    ///
+    /// As with `read_bytes`, the default implementation simply writes
+    /// one byte at a time.
+    fn write_bytes(&mut self, addr: usize, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.write(addr + i, byte);
+        }
+    }
 }
 
 /// Represents the capability for an object to by read from a "sink of
@@ -1,9 +1,57 @@
+//! "Auxiliary" trait implementations and other goodies. In particular,
+//! this module includes implementations of `MemRead` and `MemWrite` for
+//! `usize`, `isize`, and other primitives.
+
 use super::{Mem, MemRead, MemWrite};
 use std::mem::size_of;
 
-/// "Auxiliary" trait implementations and other goodies. In particular,
-/// this module includes implementations of `MemRead` and `MemWrite` for
-/// `usize`, `isize`, and other primitives.
+/// The number of bytes used to store a pointer into the heap, both in
+/// block headers and in the boxes that make up objects. This is the
+/// native width of a `usize`, unless narrowed by the `pointer-width-2`
+/// or `pointer-width-4` feature (if both are enabled, the narrower
+/// wins).
+pub const POINTER_WIDTH: usize = if cfg!(feature = "pointer-width-2") {
+    2
+} else if cfg!(feature = "pointer-width-4") {
+    4
+} else {
+    size_of::<usize>()
+};
+
+/// A pointer into the heap (or an offset or size measured within it),
+/// which is stored in `POINTER_WIDTH` bytes rather than as a full
+/// `usize`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pointer(pub usize);
+
+/// Reads a `width`-byte unsigned integer, encoded (like a `usize`) as a
+/// little-endian sequence of bytes.
+pub fn read_word<M: Mem>(mem: &M, addr: usize, width: usize) -> usize {
+    let mut out: usize = 0;
+
+    for i in 0..width {
+        out |= (mem.read(addr + i) as usize) << (i * 8);
+    }
+
+    out
+}
+
+/// Writes `value` as a `width`-byte unsigned integer (see `read_word`).
+///
+/// # Panics
+///
+/// Panics if `value` doesn't fit in `width` bytes.
+pub fn write_word<M: Mem>(mem: &mut M, addr: usize, width: usize, value: usize) {
+    if width < size_of::<usize>() && value >> (width * 8) != 0 {
+        panic!("{} doesn't fit in {} bytes", value, width);
+    }
+
+    for i in 0..width {
+        let byte = (value >> (i * 8) & 0xFF) as u8;
+
+        mem.write(addr + i, byte);
+    }
+}
 
 impl MemRead for usize {
     /// Read a `usize` as a *little-endian* encoded sequence of bytes.
@@ -62,6 +110,25 @@ impl MemWrite for usize {
     }
 }
 
+impl MemRead for Pointer {
+    fn read<M: Mem>(mem: &M, addr: usize) -> Pointer {
+        Pointer(read_word(mem, addr, POINTER_WIDTH))
+    }
+}
+
+impl MemWrite for Pointer {
+    /// # Panics
+    ///
+    /// Panics if the pointer doesn't fit in `POINTER_WIDTH` bytes.
+    fn write<M: Mem>(&self, mem: &mut M, addr: usize) {
+        write_word(mem, addr, POINTER_WIDTH, self.0);
+    }
+
+    fn size(&self) -> usize {
+        POINTER_WIDTH
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,4 +186,46 @@ mod tests {
 
         assert_eq!(usize::read(&mem, addr), bytes);
     }
+
+    #[test]
+    fn write_read_word() {
+        let mut mem: Vec<u8> = vec![0x00; 8];
+
+        write_word(&mut mem, 1, 3, 0x12_34_56);
+
+        assert_eq!(&mem[..5], [0x00, 0x56, 0x34, 0x12, 0x00]);
+        assert_eq!(read_word(&mem, 1, 3), 0x12_34_56);
+        assert_eq!(read_word(&mem, 1, 2), 0x34_56);
+    }
+
+    #[test]
+    #[should_panic(expected = "65536 doesn't fit in 2 bytes")]
+    fn write_word_too_large() {
+        let mut mem: Vec<u8> = vec![0x00; 8];
+
+        write_word(&mut mem, 0, 2, 1 << 16);
+    }
+
+    #[test]
+    fn write_read_pointer() {
+        let mut mem: Vec<u8> = vec![0xFF; 12];
+
+        Pointer(0x12_34).write(&mut mem, 2);
+
+        assert_eq!(&mem[2..4], [0x34, 0x12]);
+        assert_eq!(mem[2 + POINTER_WIDTH], 0xFF);
+        assert_eq!(Pointer::read(&mem, 2), Pointer(0x12_34));
+        assert_eq!(Pointer(0).size(), POINTER_WIDTH);
+    }
+
+    #[test]
+    #[cfg(feature = "pointer-width-2")]
+    fn two_byte_pointer() {
+        let mut mem: Vec<u8> = vec![0xFF; 4];
+
+        Pointer(0xAB_CD).write(&mut mem, 1);
+
+        assert_eq!(mem, [0xFF, 0xCD, 0xAB, 0xFF]);
+        assert_eq!(Pointer::read(&mem, 1), Pointer(0xAB_CD));
+    }
 }
@@ -1,24 +1,35 @@
+use super::aux::{read_word, write_word, POINTER_WIDTH};
 use super::{Mem, MemRead, MemWrite};
-use std::mem::size_of;
 
 /// Represents a header for a block of memory. Each header includes a
 /// pointer to the next block (`next`), its size (`size`), and several
 /// flags indicating if the block has been allocated (`allocd`), or if
 /// the block has been marked as in use during a marking phase
-/// (`marked`).
+/// (`marked`). With the `refcount-header` feature, each header also
+/// holds the reference count of its block's object (`refcount`; see
+/// `Heap::incref`). Otherwise, `refcount` isn't stored, and is always 0.
 ///
 /// # Notes
 ///
 /// The `size` of a header represents its writable capacity, and does
 /// *not* include the size of the header itself.
+///
+/// Each header is written with a trailing "magic" byte (`MAGIC`), which
+/// is checked whenever a header is read. This catches many cases in
+/// which we attempt to read a header from somewhere that doesn't
+/// actually hold one (e.g. the middle of an object).
 #[derive(Debug, PartialEq)]
 pub struct Header {
     pub next: usize,
     pub size: usize,
     pub allocd: bool,
     pub marked: bool,
+    pub refcount: usize,
 }
 
+/// The guard byte written at the end of every header.
+pub const MAGIC: u8 = 0xA5;
+
 impl Header {
     /// Creates a header for a freshly allocated block of memory.
     pub fn new(next: usize, size: usize, allocd: bool) -> Header {
@@ -27,55 +38,182 @@ impl Header {
             size,
             allocd,
             marked: false,
+            refcount: 0,
         }
     }
 }
 
 impl MemRead for Header {
+    /// # Panics
+    ///
+    /// Panics if the header's magic byte is missing, indicating that the
+    /// heap has been corrupted (or that `addr` doesn't point at a
+    /// header).
     fn read<M: Mem>(mem: &M, addr: usize) -> Header {
-        let word_size = size_of::<usize>();
+        let header_size = Header::new(0, 0, false).size();
 
-        let next = usize::read(mem, addr);
-        let size = usize::read(mem, addr + word_size);
-        let flags = mem.read(addr + 2 * word_size);
+        if mem.read(addr + header_size - 1) != MAGIC {
+            panic!("corrupt header at {}", addr);
+        }
 
-        let allocd = flags & 0b1000_0000 > 0;
-        let marked = flags & 0b0100_0000 > 0;
+        let mut header = if cfg!(feature = "compact-header") {
+            read_compact(mem, addr)
+        } else {
+            read_wide(mem, addr)
+        };
 
-        Header {
-            next,
-            size,
-            allocd,
-            marked,
-        }
+        let refcount_addr = addr + header_size - 1 - REFCOUNT_WIDTH;
+        header.refcount = read_word(mem, refcount_addr, REFCOUNT_WIDTH);
+
+        header
     }
 }
 
 impl MemWrite for Header {
+    /// # Panics
+    ///
+    /// Panics if the header has a nonzero `refcount`, but the
+    /// `refcount-header` feature isn't enabled.
     fn write<M: Mem>(&self, mem: &mut M, addr: usize) {
-        let word_size = size_of::<usize>();
-
-        self.next.write(mem, addr);
-        self.size.write(mem, addr + word_size);
+        if cfg!(feature = "compact-header") {
+            write_compact(self, mem, addr);
+        } else {
+            write_wide(self, mem, addr);
+        }
 
-        let allocd_flag = if self.allocd { 0b1000_0000 } else { 0 };
-        let marked_flag = if self.marked { 0b0100_0000 } else { 0 };
+        if REFCOUNT_WIDTH == 0 && self.refcount != 0 {
+            panic!(
+                "Header at {} has a refcount, but refcounts aren't stored",
+                addr
+            );
+        }
 
-        let flags = allocd_flag | marked_flag;
+        let refcount_addr = addr + self.size() - 1 - REFCOUNT_WIDTH;
+        write_word(mem, refcount_addr, REFCOUNT_WIDTH, self.refcount);
 
-        mem.write(addr + 2 * word_size, flags);
+        mem.write(addr + self.size() - 1, MAGIC);
     }
 
     fn size(&self) -> usize {
-        size_of::<usize>() + size_of::<usize>() + 1
+        let fields_size = if cfg!(feature = "compact-header") {
+            2 * COMPACT_WIDTH
+        } else {
+            2 * POINTER_WIDTH + 1
+        };
+
+        fields_size + REFCOUNT_WIDTH + 1
+    }
+}
+
+/// The flag bits indicating that a block is allocated or marked, in the
+/// flags byte of a wide header.
+const ALLOCD_FLAG: u8 = 0b1000_0000;
+const MARKED_FLAG: u8 = 0b0100_0000;
+
+/// The width of each of the two fields of a compact header: 32 bits, or
+/// less if pointers are narrower than that.
+const COMPACT_WIDTH: usize = if POINTER_WIDTH < 4 { POINTER_WIDTH } else { 4 };
+
+/// In compact headers, the flags are packed into the high bits of the
+/// size, which leaves the rest (30 bits, with 32-bit fields) for the
+/// size itself.
+const COMPACT_ALLOCD_FLAG: usize = 1 << (8 * COMPACT_WIDTH - 1);
+const COMPACT_MARKED_FLAG: usize = 1 << (8 * COMPACT_WIDTH - 2);
+const COMPACT_SIZE_MASK: usize = COMPACT_MARKED_FLAG - 1;
+
+/// The width of the reference count, which (with the `refcount-header`
+/// feature) follows the other fields, and is as wide as each of them.
+const REFCOUNT_WIDTH: usize = if !cfg!(feature = "refcount-header") {
+    0
+} else if cfg!(feature = "compact-header") {
+    COMPACT_WIDTH
+} else {
+    POINTER_WIDTH
+};
+
+/// Reads a "wide" header: `next` and `size` are full pointers (see
+/// `POINTER_WIDTH`), followed by a byte of flags.
+fn read_wide<M: Mem>(mem: &M, addr: usize) -> Header {
+    let next = read_word(mem, addr, POINTER_WIDTH);
+    let size = read_word(mem, addr + POINTER_WIDTH, POINTER_WIDTH);
+    let flags = mem.read(addr + 2 * POINTER_WIDTH);
+
+    let allocd = flags & ALLOCD_FLAG > 0;
+    let marked = flags & MARKED_FLAG > 0;
+
+    Header {
+        next,
+        size,
+        allocd,
+        marked,
+        refcount: 0,
     }
 }
 
+fn write_wide<M: Mem>(header: &Header, mem: &mut M, addr: usize) {
+    write_word(mem, addr, POINTER_WIDTH, header.next);
+    write_word(mem, addr + POINTER_WIDTH, POINTER_WIDTH, header.size);
+
+    let allocd_flag = if header.allocd { ALLOCD_FLAG } else { 0 };
+    let marked_flag = if header.marked { MARKED_FLAG } else { 0 };
+
+    let flags = allocd_flag | marked_flag;
+
+    mem.write(addr + 2 * POINTER_WIDTH, flags);
+}
+
+/// Reads a "compact" header (enabled by the `compact-header` feature):
+/// `next` is a 32-bit offset, and `size` is a 30-bit length sharing a
+/// 32-bit word with the flags. (With 2-byte pointers, these shrink to a
+/// 16-bit offset and a 14-bit length.)
+fn read_compact<M: Mem>(mem: &M, addr: usize) -> Header {
+    let next = read_word(mem, addr, COMPACT_WIDTH);
+    let size_and_flags = read_word(mem, addr + COMPACT_WIDTH, COMPACT_WIDTH);
+
+    Header {
+        next,
+        size: size_and_flags & COMPACT_SIZE_MASK,
+        allocd: size_and_flags & COMPACT_ALLOCD_FLAG > 0,
+        marked: size_and_flags & COMPACT_MARKED_FLAG > 0,
+        refcount: 0,
+    }
+}
+
+/// # Panics
+///
+/// Panics if `next` or `size` doesn't fit in the compact layout, which
+/// limits heaps to 1GiB (or 16KiB, with 2-byte pointers).
+fn write_compact<M: Mem>(header: &Header, mem: &mut M, addr: usize) {
+    if header.next >> (8 * COMPACT_WIDTH) != 0 || header.size > COMPACT_SIZE_MASK {
+        panic!("Header at {} is too large for the compact layout", addr);
+    }
+
+    let allocd_flag = if header.allocd {
+        COMPACT_ALLOCD_FLAG
+    } else {
+        0
+    };
+    let marked_flag = if header.marked {
+        COMPACT_MARKED_FLAG
+    } else {
+        0
+    };
+
+    write_word(mem, addr, COMPACT_WIDTH, header.next);
+    write_word(
+        mem,
+        addr + COMPACT_WIDTH,
+        COMPACT_WIDTH,
+        header.size | allocd_flag | marked_flag,
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[cfg(not(feature = "pointer-width-2"))]
     fn write_read_header() {
         let mut mem: Vec<u8> = vec![0x00; 128];
 
@@ -84,6 +222,7 @@ mod tests {
             size: 7813423,
             allocd: true,
             marked: false,
+            refcount: 0,
         };
         let addr = 34;
 
@@ -92,9 +231,102 @@ mod tests {
         assert_eq!(Header::read(&mem, addr), header);
     }
 
+    #[test]
+    #[cfg(not(feature = "pointer-width-2"))]
+    fn write_read_marked_header() {
+        let mut mem: Vec<u8> = vec![0x00; 64];
+
+        let header = Header {
+            next: 41,
+            size: 1 << 20,
+            allocd: false,
+            marked: true,
+            refcount: 0,
+        };
+
+        header.write(&mut mem, 7);
+
+        assert_eq!(Header::read(&mem, 7), header);
+    }
+
+    #[test]
+    #[cfg(feature = "refcount-header")]
+    fn write_read_refcounted_header() {
+        let mut mem: Vec<u8> = vec![0x00; 64];
+
+        let mut header = Header::new(40, 12, true);
+        header.refcount = 3;
+        header.write(&mut mem, 5);
+
+        assert_eq!(Header::read(&mem, 5), header);
+    }
+
+    #[test]
+    #[cfg(not(feature = "refcount-header"))]
+    #[should_panic(expected = "refcounts aren't stored")]
+    fn write_unstored_refcount() {
+        let mut mem: Vec<u8> = vec![0x00; 64];
+
+        let mut header = Header::new(40, 12, true);
+        header.refcount = 1;
+        header.write(&mut mem, 5);
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "compact-header",
+        not(feature = "pointer-width-2"),
+        not(feature = "refcount-header")
+    ))]
+    fn compact_header_size() {
+        assert_eq!(Header::new(0, 0, false).size(), 9);
+    }
+
+    #[test]
+    #[cfg(feature = "pointer-width-2")]
+    fn two_byte_header() {
+        let mut mem: Vec<u8> = vec![0x00; 16];
+
+        let header = Header {
+            next: 0xFF_F0,
+            size: 0x3F_FF,
+            allocd: true,
+            marked: true,
+            refcount: 0,
+        };
+        header.write(&mut mem, 3);
+
+        let fields_size = if cfg!(feature = "compact-header") {
+            5
+        } else {
+            6
+        };
+        let refcount_size = if cfg!(feature = "refcount-header") {
+            2
+        } else {
+            0
+        };
+        let expected_size = fields_size + refcount_size;
+        assert_eq!(header.size(), expected_size);
+        assert_eq!(Header::read(&mem, 3), header);
+        assert_eq!(mem[3 + expected_size..], vec![0x00; 13 - expected_size][..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt header at 3")]
+    fn read_corrupt_header() {
+        let mut mem: Vec<u8> = vec![0x00; 64];
+
+        let header = Header::new(12, 34, false);
+        header.write(&mut mem, 3);
+        mem[3 + header.size() - 1] = 0x00;
+
+        Header::read(&mem, 3);
+    }
+
     #[cfg(test)]
     impl Mem for Vec<u8> {
-        fn alloc<T: MemWrite>(&mut self, obj: &T) -> usize {
+        fn alloc<T: MemWrite>(&mut self, _obj: &T) -> usize {
             0
         }
 
@@ -0,0 +1,1487 @@
+//! Primitive procedures. Each primitive receives the addresses of its
+//! (already evaluated) arguments, and returns the address of its result,
+//! allocating it on the heap if necessary.
+
+use crate::data::{
+    car, cdr, cons, make_vector, scheme_eq, scheme_eqv, set_car, set_cdr, vector_length,
+    vector_set, SchemeObj, Tag,
+};
+use crate::heap::Heap;
+use crate::list::{nth_tail, ListIter};
+use crate::memory::{Mem, MemRead};
+use crate::print::write_flat;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+
+/// Represents the ways in which evaluation (and, in particular, the
+/// application of a primitive) can fail.
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    /// A procedure was applied to the wrong number of arguments. For
+    /// procedures accepting a variable number of arguments, `expected` is
+    /// the minimum.
+    Arity { expected: usize, got: usize },
+    /// An argument that should have been a number (at the indicated
+    /// address) wasn't.
+    NotANumber(usize),
+    /// An argument that should have been an integer (at the indicated
+    /// address) wasn't.
+    NotAnInteger(usize),
+    /// An argument that should have been a pair (at the indicated
+    /// address) wasn't.
+    NotAPair(usize),
+    /// An argument that should have been a proper list (at the indicated
+    /// address) wasn't.
+    NotAList(usize),
+    /// An argument that should have been a vector (at the indicated
+    /// address) wasn't.
+    NotAVector(usize),
+    /// An argument that should have been a character (at the indicated
+    /// address) wasn't.
+    NotAChar(usize),
+    /// An object that was applied as a procedure (at the indicated
+    /// address) wasn't one.
+    NotAProcedure(usize),
+    /// An argument (at the indicated address) was outside the range of
+    /// values that a primitive accepts.
+    OutOfRange(usize),
+    /// The result of an arithmetic operation didn't fit in a fixnum.
+    Overflow,
+    /// A number was divided by zero.
+    DivideByZero,
+    /// Writing to an output sink failed.
+    Output(io::ErrorKind),
+}
+
+/// The signature shared by every primitive procedure.
+pub type Prim = fn(&mut Heap, &[usize]) -> Result<usize, EvalError>;
+
+/// Every primitive that can be referred to from the heap, by name. A
+/// `SchemeObj::Primitive` holds an index into this table.
+pub const PRIMITIVES: &[(&str, Prim)] = &[
+    ("+", prim_add),
+    ("-", prim_sub),
+    ("*", prim_mul),
+    ("expt", prim_expt),
+    ("quotient", prim_quotient),
+    ("remainder", prim_remainder),
+    ("modulo", prim_modulo),
+    ("=", prim_num_eq),
+    ("<", prim_lt),
+    (">", prim_gt),
+    ("<=", prim_le),
+    (">=", prim_ge),
+    ("not", prim_not),
+    ("null?", prim_is_null),
+    ("pair?", prim_is_pair),
+    ("zero?", prim_is_zero),
+    ("cons", prim_cons),
+    ("car", prim_car),
+    ("cdr", prim_cdr),
+    ("eq?", prim_eq),
+    ("eqv?", prim_eqv),
+    ("set-car!", prim_set_car),
+    ("set-cdr!", prim_set_cdr),
+    ("length", prim_length),
+    ("reverse", prim_reverse),
+    ("memq", prim_memq),
+    ("assq", prim_assq),
+    ("make-vector", prim_make_vector),
+    ("vector-fill!", prim_vector_fill),
+    ("char->integer", prim_char_to_integer),
+    ("integer->char", prim_integer_to_char),
+];
+
+/// Returns the (interned) procedure object for the primitive named
+/// `name`, or `None` if there is no such primitive. As it's interned,
+/// every lookup of the same primitive yields the same address.
+pub fn primitive(mem: &mut Heap, name: &str) -> Option<usize> {
+    let index = PRIMITIVES.iter().position(|&(n, _)| n == name)?;
+
+    Some(mem.intern_constant(&SchemeObj::Primitive(index)))
+}
+
+/// Applies the procedure object at `proc_addr` to the arguments at
+/// `arg_addrs`.
+pub fn call(mem: &mut Heap, proc_addr: usize, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    match SchemeObj::read(mem, proc_addr) {
+        SchemeObj::Primitive(index) => (PRIMITIVES[index].1)(mem, arg_addrs),
+        _ => Err(EvalError::NotAProcedure(proc_addr)),
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EvalError::*;
+
+        match self {
+            Arity { expected, got } => write!(
+                f,
+                "wrong number of arguments: expected {}, got {}",
+                expected, got
+            ),
+            NotANumber(addr) => write!(f, "expected a number at {}", addr),
+            NotAnInteger(addr) => write!(f, "expected an integer at {}", addr),
+            NotAPair(addr) => write!(f, "expected a pair at {}", addr),
+            NotAList(addr) => write!(f, "expected a proper list at {}", addr),
+            NotAVector(addr) => write!(f, "expected a vector at {}", addr),
+            NotAChar(addr) => write!(f, "expected a character at {}", addr),
+            NotAProcedure(addr) => write!(f, "expected a procedure at {}", addr),
+            OutOfRange(addr) => write!(f, "argument out of range at {}", addr),
+            Overflow => write!(f, "arithmetic overflow"),
+            DivideByZero => write!(f, "division by zero"),
+            Output(kind) => write!(f, "failed to write output: {}", kind),
+        }
+    }
+}
+
+/// `(+ n ...)`: sums its arguments. `(+)` is 0.
+pub fn prim_add(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let nums = number_args(mem, arg_addrs)?;
+    let sum = nums
+        .iter()
+        .try_fold((0, 1), |acc, &n| ratio_add(acc, n))
+        .ok_or(EvalError::Overflow)?;
+
+    alloc_ratio(mem, sum)
+}
+
+/// `(- n m ...)`: subtracts each of the remaining arguments from the
+/// first. With a single argument, `(- n)` negates it.
+pub fn prim_sub(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let nums = number_args(mem, arg_addrs)?;
+
+    let diff = match nums.split_first() {
+        None => return Err(arity_error(1, nums.len())),
+        Some((&n, [])) => ratio_sub((0, 1), n),
+        Some((&n, rest)) => rest.iter().try_fold(n, |acc, &m| ratio_sub(acc, m)),
+    }
+    .ok_or(EvalError::Overflow)?;
+
+    alloc_ratio(mem, diff)
+}
+
+/// `(* n ...)`: multiplies its arguments. `(*)` is 1.
+pub fn prim_mul(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let nums = number_args(mem, arg_addrs)?;
+    let product = nums
+        .iter()
+        .try_fold((1, 1), |acc, &n| ratio_mul(acc, n))
+        .ok_or(EvalError::Overflow)?;
+
+    alloc_ratio(mem, product)
+}
+
+/// `(expt b e)`: raises the integer `b` to the integer power `e`. A
+/// negative exponent yields the reciprocal of `(expt b (- e))`.
+pub fn prim_expt(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let nums = integer_args(mem, arg_addrs)?;
+    let (base, exp) = (nums[0], nums[1]);
+
+    let power = checked_pow(base, exp.unsigned_abs()).ok_or(EvalError::Overflow)?;
+
+    if exp >= 0 {
+        return Ok(mem.alloc_obj(&SchemeObj::Number(power)));
+    }
+
+    if power == 0 {
+        return Err(EvalError::DivideByZero);
+    }
+
+    alloc_ratio(mem, (1, power))
+}
+
+/// `(quotient n d)`: divides `n` by `d`, truncating towards zero.
+pub fn prim_quotient(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let (n, d) = division_args(mem, arg_addrs)?;
+    let quotient = n.checked_div(d).ok_or(EvalError::Overflow)?;
+
+    Ok(mem.alloc_obj(&SchemeObj::Number(quotient)))
+}
+
+/// `(remainder n d)`: the remainder of `(quotient n d)`, which has the
+/// same sign as `n`.
+pub fn prim_remainder(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let (n, d) = division_args(mem, arg_addrs)?;
+
+    Ok(mem.alloc_obj(&SchemeObj::Number(n.wrapping_rem(d))))
+}
+
+/// `(modulo n d)`: the remainder of dividing `n` by `d` while rounding
+/// down, which has the same sign as `d`.
+pub fn prim_modulo(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    let (n, d) = division_args(mem, arg_addrs)?;
+
+    let mut modulo = n.wrapping_rem(d);
+    if modulo != 0 && (modulo < 0) != (d < 0) {
+        modulo += d;
+    }
+
+    Ok(mem.alloc_obj(&SchemeObj::Number(modulo)))
+}
+
+/// `(= n m ...)`: determines if all of its arguments are numerically
+/// equal.
+pub fn prim_num_eq(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    compare_chain(mem, arg_addrs, |n, m| n == m)
+}
+
+/// `(< n m ...)`: determines if its arguments are strictly increasing.
+pub fn prim_lt(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    compare_chain(mem, arg_addrs, |n, m| n < m)
+}
+
+/// `(> n m ...)`: determines if its arguments are strictly decreasing.
+pub fn prim_gt(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    compare_chain(mem, arg_addrs, |n, m| n > m)
+}
+
+/// `(<= n m ...)`: determines if its arguments are nondecreasing.
+pub fn prim_le(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    compare_chain(mem, arg_addrs, |n, m| n <= m)
+}
+
+/// `(>= n m ...)`: determines if its arguments are nonincreasing.
+pub fn prim_ge(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    compare_chain(mem, arg_addrs, |n, m| n >= m)
+}
+
+/// `(not x)`: determines if `x` is `#f`. Every other object (including
+/// `'()`) counts as true.
+pub fn prim_not(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let addr = arg_addrs[0];
+    let is_false = Tag::from(mem.read(addr)) == Tag::Bool && mem.read(addr + 1) == 0;
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(is_false)))
+}
+
+/// `(null? x)`: determines if `x` is the empty list.
+pub fn prim_is_null(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let is_null = Tag::from(mem.read(arg_addrs[0])) == Tag::Nil;
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(is_null)))
+}
+
+/// `(pair? x)`: determines if `x` is a pair.
+pub fn prim_is_pair(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let is_pair = Tag::from(mem.read(arg_addrs[0])) == Tag::Pair;
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(is_pair)))
+}
+
+/// `(zero? n)`: determines if the number `n` is 0.
+pub fn prim_is_zero(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let nums = number_args(mem, arg_addrs)?;
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(nums[0].0 == 0)))
+}
+
+/// `(cons a b)`: allocates a new pair whose car is `a` and whose cdr is
+/// `b`.
+pub fn prim_cons(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+
+    Ok(cons(mem, arg_addrs[0], arg_addrs[1]))
+}
+
+/// `(car p)`: returns the car of the pair `p`.
+pub fn prim_car(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let pair = pair_arg(mem, arg_addrs[0])?;
+
+    Ok(car(mem, pair))
+}
+
+/// `(cdr p)`: returns the cdr of the pair `p`.
+pub fn prim_cdr(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let pair = pair_arg(mem, arg_addrs[0])?;
+
+    Ok(cdr(mem, pair))
+}
+
+/// `(eq? a b)`: determines if `a` and `b` are the same object.
+pub fn prim_eq(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let same = scheme_eq(mem, arg_addrs[0], arg_addrs[1]);
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(same)))
+}
+
+/// `(eqv? a b)`: like `eq?`, but also considers equal numbers to be
+/// equivalent.
+pub fn prim_eqv(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let same = scheme_eqv(mem, arg_addrs[0], arg_addrs[1]);
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(same)))
+}
+
+/// `(set-car! p x)`: points the car of the pair `p` at `x`, in place.
+pub fn prim_set_car(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let pair = pair_arg(mem, arg_addrs[0])?;
+
+    set_car(mem, pair, arg_addrs[1]);
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// `(set-cdr! p x)`: points the cdr of the pair `p` at `x`, in place.
+pub fn prim_set_cdr(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let pair = pair_arg(mem, arg_addrs[0])?;
+
+    set_cdr(mem, pair, arg_addrs[1]);
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// `(length list)`: counts the elements of the proper list `list`.
+pub fn prim_length(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let elems = list_arg(mem, arg_addrs[0])?;
+
+    Ok(mem.alloc_obj(&SchemeObj::Number(elems.len() as isize)))
+}
+
+/// `(reverse list)`: allocates a new list holding the elements of the
+/// proper list `list`, in reverse order.
+pub fn prim_reverse(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let elems = list_arg(mem, arg_addrs[0])?;
+
+    let mut result = mem.nil();
+    for elem in elems {
+        // Allocating the next pair may trigger a collection, during which
+        // the list we've built so far is only held here.
+        mem.push_temp_root(result);
+        let pair = cons(mem, elem, result);
+        mem.pop_temp_root();
+
+        result = pair;
+    }
+
+    Ok(result)
+}
+
+/// `(memq key list)`: returns the first tail of `list` whose car is
+/// `eq?` to `key`, or `#f` if there isn't one.
+///
+/// # Notes
+///
+/// Numbers are compared by value (as with `eqv?`), since whether two
+/// equal numbers are `eq?` depends on how they happened to be allocated.
+pub fn prim_memq(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let (key, list) = (arg_addrs[0], arg_addrs[1]);
+
+    let mut iter = ListIter::new(mem, list);
+    let found = iter.position(|elem| scheme_eqv(mem, key, elem));
+
+    match found {
+        Some(i) => Ok(nth_tail(mem, list, i)),
+        None if iter.is_proper() == Some(true) => Ok(mem.intern_constant(&SchemeObj::Bool(false))),
+        None => Err(EvalError::NotAList(list)),
+    }
+}
+
+/// `(assq key alist)`: returns the first pair in the association list
+/// `alist` whose car is `eq?` to `key`, or `#f` if there isn't one. As
+/// with `memq`, numbers are compared by value.
+pub fn prim_assq(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let (key, alist) = (arg_addrs[0], arg_addrs[1]);
+
+    let mut iter = ListIter::new(mem, alist);
+    for entry in iter.by_ref() {
+        let entry = pair_arg(mem, entry)?;
+
+        if scheme_eqv(mem, key, car(mem, entry)) {
+            return Ok(entry);
+        }
+    }
+
+    match iter.is_proper() {
+        Some(true) => Ok(mem.intern_constant(&SchemeObj::Bool(false))),
+        _ => Err(EvalError::NotAList(alist)),
+    }
+}
+
+/// `(make-vector k fill)`: allocates a vector of `k` slots, each holding
+/// `fill`. If `fill` is omitted, the slots hold an unspecified value.
+pub fn prim_make_vector(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    if arg_addrs.is_empty() || arg_addrs.len() > 2 {
+        return Err(arity_error(1, arg_addrs.len()));
+    }
+
+    let len = integer_args(mem, &arg_addrs[..1])?[0];
+    if len < 0 {
+        return Err(EvalError::OutOfRange(arg_addrs[0]));
+    }
+
+    let fill = match arg_addrs.get(1) {
+        Some(&fill) => fill,
+        None => mem.intern_constant(&SchemeObj::Unspecified),
+    };
+
+    Ok(make_vector(mem, len as usize, fill))
+}
+
+/// `(vector-fill! v fill)`: points every slot of the vector `v` at
+/// `fill`, in place.
+pub fn prim_vector_fill(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let vector = vector_arg(mem, arg_addrs[0])?;
+
+    for i in 0..vector_length(mem, vector) {
+        vector_set(mem, vector, i, arg_addrs[1]);
+    }
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// `(char->integer c)`: returns the Unicode scalar value of the
+/// character `c`.
+pub fn prim_char_to_integer(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let c = char_arg(mem, arg_addrs[0])?;
+
+    Ok(mem.alloc_obj(&SchemeObj::Number(c as isize)))
+}
+
+/// `(integer->char n)`: returns the character whose Unicode scalar value
+/// is `n`. Surrogates (`#xD800` through `#xDFFF`) aren't scalar values,
+/// so they're rejected along with anything past `#x10FFFF`.
+pub fn prim_integer_to_char(mem: &mut Heap, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+    let n = integer_args(mem, arg_addrs)?[0];
+
+    let c = u32::try_from(n)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or(EvalError::OutOfRange(arg_addrs[0]))?;
+
+    Ok(mem.alloc_obj(&SchemeObj::Char(c)))
+}
+
+/// `(apply f a ... args)`: applies the procedure object at `proc_addr`
+/// to the arguments `a ...`, followed by the elements of the proper list
+/// `args`.
+///
+/// # Notes
+///
+/// Spreading `args` only reads the heap, so no collection can occur
+/// before the procedure is called with the (still valid) element
+/// addresses.
+pub fn apply(mem: &mut Heap, proc_addr: usize, arg_addrs: &[usize]) -> Result<usize, EvalError> {
+    procedure_arg(mem, proc_addr)?;
+
+    let (&list, leading) = match arg_addrs.split_last() {
+        Some(split) => split,
+        None => return Err(arity_error(1, 0)),
+    };
+
+    let mut args = leading.to_vec();
+    args.extend(list_arg(mem, list)?);
+
+    call(mem, proc_addr, &args)
+}
+
+/// `(map f list)`: allocates a list of the results of applying the
+/// procedure object at `proc_addr` to each element of the proper list
+/// `list`.
+///
+/// # Notes
+///
+/// Each application may trigger a collection, so the results gathered
+/// so far are kept as temporary roots until they've been linked into the
+/// result list. (The procedure and `list` must be rooted by the caller.)
+pub fn map(mem: &mut Heap, proc_addr: usize, list: usize) -> Result<usize, EvalError> {
+    procedure_arg(mem, proc_addr)?;
+    let elems = list_arg(mem, list)?;
+
+    let mut results = vec![];
+    let mut outcome = Ok(());
+    for elem in elems {
+        match call(mem, proc_addr, &[elem]) {
+            Ok(result) => {
+                mem.push_temp_root(result);
+                results.push(result);
+            }
+            Err(err) => {
+                outcome = Err(err);
+                break;
+            }
+        }
+    }
+
+    let mut list = mem.nil();
+    if outcome.is_ok() {
+        for &result in results.iter().rev() {
+            mem.push_temp_root(list);
+            let pair = cons(mem, result, list);
+            mem.pop_temp_root();
+
+            list = pair;
+        }
+    }
+
+    for _ in &results {
+        mem.pop_temp_root();
+    }
+
+    outcome.map(|()| list)
+}
+
+/// `(for-each f list)`: applies the procedure object at `proc_addr` to
+/// each element of the proper list `list`, for its side effects.
+pub fn for_each(mem: &mut Heap, proc_addr: usize, list: usize) -> Result<usize, EvalError> {
+    procedure_arg(mem, proc_addr)?;
+
+    for elem in list_arg(mem, list)? {
+        call(mem, proc_addr, &[elem])?;
+    }
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// `(display obj)`: writes a rendering of `obj` to `out`.
+///
+/// # Notes
+///
+/// There's no evaluation context to hold an output port yet, so the sink
+/// is passed in directly. And as there are no
+/// strings or characters, which are the only objects that `display` and
+/// `write` render differently, `obj` is rendered as by `write_flat`.
+pub fn display(
+    mem: &mut Heap,
+    out: &mut dyn Write,
+    arg_addrs: &[usize],
+) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 1)?;
+
+    write!(out, "{}", write_flat(mem, arg_addrs[0]))
+        .map_err(|err| EvalError::Output(err.kind()))?;
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// `(newline)`: writes a newline to `out`.
+pub fn newline(
+    mem: &mut Heap,
+    out: &mut dyn Write,
+    arg_addrs: &[usize],
+) -> Result<usize, EvalError> {
+    check_arity(arg_addrs, 0)?;
+
+    writeln!(out).map_err(|err| EvalError::Output(err.kind()))?;
+
+    Ok(mem.intern_constant(&SchemeObj::Unspecified))
+}
+
+/// An exact number, as a numerator and a positive denominator in lowest
+/// terms. Integers have a denominator of 1.
+type Ratio = (isize, isize);
+
+/// Reads each of the arguments at `arg_addrs` as an exact number.
+fn number_args(mem: &Heap, arg_addrs: &[usize]) -> Result<Vec<Ratio>, EvalError> {
+    arg_addrs
+        .iter()
+        .map(|&addr| match SchemeObj::read(mem, addr) {
+            SchemeObj::Number(n) => Ok((n, 1)),
+            SchemeObj::Rational { num, den } => Ok((num, den)),
+            _ => Err(EvalError::NotANumber(addr)),
+        })
+        .collect()
+}
+
+/// Reads each of the arguments at `arg_addrs` as an integer.
+fn integer_args(mem: &Heap, arg_addrs: &[usize]) -> Result<Vec<isize>, EvalError> {
+    arg_addrs
+        .iter()
+        .map(|&addr| match Tag::from(mem.read(addr)) {
+            Tag::Number => Ok(usize::read(mem, addr + 1) as isize),
+            Tag::Rational => Err(EvalError::NotAnInteger(addr)),
+            _ => Err(EvalError::NotANumber(addr)),
+        })
+        .collect()
+}
+
+/// Reduces `num / den` to lowest terms, or returns `None` if the result
+/// doesn't fit.
+fn ratio(num: isize, den: isize) -> Option<Ratio> {
+    match SchemeObj::rational(num, den)? {
+        SchemeObj::Number(n) => Some((n, 1)),
+        SchemeObj::Rational { num, den } => Some((num, den)),
+        _ => unreachable!(),
+    }
+}
+
+/// Adds two exact numbers, or returns `None` on overflow.
+fn ratio_add((a, b): Ratio, (c, d): Ratio) -> Option<Ratio> {
+    let num = a.checked_mul(d)?.checked_add(c.checked_mul(b)?)?;
+    ratio(num, b.checked_mul(d)?)
+}
+
+/// Multiplies two exact numbers, or returns `None` on overflow.
+fn ratio_mul((a, b): Ratio, (c, d): Ratio) -> Option<Ratio> {
+    ratio(a.checked_mul(c)?, b.checked_mul(d)?)
+}
+
+/// Subtracts one exact number from another, or returns `None` on
+/// overflow.
+fn ratio_sub((a, b): Ratio, (c, d): Ratio) -> Option<Ratio> {
+    let num = a.checked_mul(d)?.checked_sub(c.checked_mul(b)?)?;
+    ratio(num, b.checked_mul(d)?)
+}
+
+/// Allocates an exact number, as an integer if its denominator is 1.
+fn alloc_ratio(mem: &mut Heap, (num, den): Ratio) -> Result<usize, EvalError> {
+    let obj = SchemeObj::rational(num, den).ok_or(EvalError::Overflow)?;
+
+    Ok(mem.alloc_obj(&obj))
+}
+
+/// Determines if each adjacent pair of the (numeric) arguments at
+/// `arg_addrs` satisfies `related`. A single argument trivially does.
+fn compare_chain(
+    mem: &mut Heap,
+    arg_addrs: &[usize],
+    related: fn(i128, i128) -> bool,
+) -> Result<usize, EvalError> {
+    let nums = number_args(mem, arg_addrs)?;
+
+    if nums.is_empty() {
+        return Err(arity_error(1, 0));
+    }
+
+    // Since both denominators are positive, `a/b` and `c/d` are related
+    // just as `a*d` and `c*b` are (which can't overflow an `i128`).
+    let holds = nums.windows(2).all(|pair| {
+        let ((a, b), (c, d)) = (pair[0], pair[1]);
+        related(a as i128 * d as i128, c as i128 * b as i128)
+    });
+
+    Ok(mem.intern_constant(&SchemeObj::Bool(holds)))
+}
+
+/// Reads the dividend and (nonzero) divisor passed to one of the
+/// division primitives.
+fn division_args(mem: &Heap, arg_addrs: &[usize]) -> Result<(isize, isize), EvalError> {
+    check_arity(arg_addrs, 2)?;
+    let nums = integer_args(mem, arg_addrs)?;
+
+    match nums[1] {
+        0 => Err(EvalError::DivideByZero),
+        d => Ok((nums[0], d)),
+    }
+}
+
+/// Raises `base` to the power `exp` by repeated squaring, or returns
+/// `None` on overflow.
+fn checked_pow(mut base: isize, mut exp: usize) -> Option<isize> {
+    let mut acc = 1isize;
+
+    loop {
+        if exp & 1 == 1 {
+            acc = acc.checked_mul(base)?;
+        }
+
+        exp >>= 1;
+        if exp == 0 {
+            return Some(acc);
+        }
+
+        base = base.checked_mul(base)?;
+    }
+}
+
+/// Ensures that the argument at `addr` is a proper list, returning the
+/// addresses of its elements.
+fn list_arg(mem: &Heap, addr: usize) -> Result<Vec<usize>, EvalError> {
+    let mut iter = ListIter::new(mem, addr);
+    let elems = iter.by_ref().collect();
+
+    match iter.is_proper() {
+        Some(true) => Ok(elems),
+        _ => Err(EvalError::NotAList(addr)),
+    }
+}
+
+/// Ensures that the argument at `addr` is a pair, returning its address.
+fn pair_arg(mem: &Heap, addr: usize) -> Result<usize, EvalError> {
+    match Tag::from(mem.read(addr)) {
+        Tag::Pair => Ok(addr),
+        _ => Err(EvalError::NotAPair(addr)),
+    }
+}
+
+/// Ensures that the argument at `addr` is a vector, returning its
+/// address.
+fn vector_arg(mem: &Heap, addr: usize) -> Result<usize, EvalError> {
+    match Tag::from(mem.read(addr)) {
+        Tag::Vector => Ok(addr),
+        _ => Err(EvalError::NotAVector(addr)),
+    }
+}
+
+/// Ensures that the argument at `addr` is a character, returning it.
+fn char_arg(mem: &Heap, addr: usize) -> Result<char, EvalError> {
+    match Tag::from(mem.read(addr)) {
+        Tag::Char => match SchemeObj::read(mem, addr) {
+            SchemeObj::Char(c) => Ok(c),
+            _ => unreachable!(),
+        },
+        _ => Err(EvalError::NotAChar(addr)),
+    }
+}
+
+/// Ensures that the argument at `addr` is a procedure, returning its
+/// address.
+fn procedure_arg(mem: &Heap, addr: usize) -> Result<usize, EvalError> {
+    match Tag::from(mem.read(addr)) {
+        Tag::Primitive => Ok(addr),
+        _ => Err(EvalError::NotAProcedure(addr)),
+    }
+}
+
+/// Ensures that exactly `expected` arguments were provided.
+fn check_arity(arg_addrs: &[usize], expected: usize) -> Result<(), EvalError> {
+    if arg_addrs.len() == expected {
+        Ok(())
+    } else {
+        Err(arity_error(expected, arg_addrs.len()))
+    }
+}
+
+fn arity_error(expected: usize, got: usize) -> EvalError {
+    EvalError::Arity { expected, got }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SchemeObj::*;
+
+    fn nums(mem: &mut Heap, ns: &[isize]) -> Vec<usize> {
+        ns.iter().map(|&n| mem.alloc_obj(&Number(n))).collect()
+    }
+
+    fn read_number(mem: &Heap, addr: usize) -> isize {
+        match SchemeObj::read(mem, addr) {
+            Number(n) => n,
+            _ => panic!("Expected Number at {}", addr),
+        }
+    }
+
+    #[test]
+    fn add() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[1, 2, 3]);
+        let sum = prim_add(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, sum), 6);
+
+        let sum = prim_add(&mut mem, &[]).unwrap();
+        assert_eq!(read_number(&mem, sum), 0);
+    }
+
+    #[test]
+    fn sub() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[5]);
+        let diff = prim_sub(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, diff), -5);
+
+        let args = nums(&mut mem, &[10, 3, 2]);
+        let diff = prim_sub(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, diff), 5);
+
+        assert_eq!(prim_sub(&mut mem, &[]), Err(arity_error(1, 0)));
+    }
+
+    #[test]
+    fn mul() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[2, 3, 4]);
+        let product = prim_mul(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, product), 24);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[isize::MAX, 1]);
+        assert_eq!(prim_add(&mut mem, &args), Err(EvalError::Overflow));
+        assert_eq!(
+            prim_mul(&mut mem, &[args[0], args[0]]),
+            Err(EvalError::Overflow)
+        );
+
+        let args = nums(&mut mem, &[isize::MIN]);
+        assert_eq!(prim_sub(&mut mem, &args), Err(EvalError::Overflow));
+
+        let args = nums(&mut mem, &[isize::MIN, 1]);
+        assert_eq!(prim_sub(&mut mem, &args), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn rational_arithmetic() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let half = mem.alloc_obj(&SchemeObj::rational(1, 2).unwrap());
+        let one = mem.alloc_obj(&Number(1));
+
+        let sum = prim_add(&mut mem, &[half, one]).unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, sum),
+            SchemeObj::rational(3, 2).unwrap()
+        );
+
+        let sum = prim_add(&mut mem, &[half, half]).unwrap();
+        assert_eq!(read_number(&mem, sum), 1);
+
+        let diff = prim_sub(&mut mem, &[one, half, half]).unwrap();
+        assert_eq!(read_number(&mem, diff), 0);
+
+        let diff = prim_sub(&mut mem, &[half]).unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, diff),
+            SchemeObj::rational(-1, 2).unwrap()
+        );
+
+        let product = prim_mul(&mut mem, &[half, half]).unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, product),
+            SchemeObj::rational(1, 4).unwrap()
+        );
+
+        assert_eq!(
+            prim_quotient(&mut mem, &[one, half]),
+            Err(EvalError::NotAnInteger(half))
+        );
+    }
+
+    #[test]
+    fn rational_comparisons() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let half = mem.alloc_obj(&SchemeObj::rational(1, 2).unwrap());
+        let other_half = mem.alloc_obj(&SchemeObj::rational(2, 4).unwrap());
+        let one = mem.alloc_obj(&Number(1));
+
+        let result = prim_is_zero(&mut mem, &[half]).unwrap();
+        assert!(!read_bool(&mem, result));
+
+        let result = prim_num_eq(&mut mem, &[half, other_half]).unwrap();
+        assert!(read_bool(&mem, result));
+
+        let result = prim_lt(&mut mem, &[half, one]).unwrap();
+        assert!(read_bool(&mem, result));
+
+        let result = prim_gt(&mut mem, &[half, one]).unwrap();
+        assert!(!read_bool(&mem, result));
+    }
+
+    #[test]
+    fn expt() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[-3, 3]);
+        let power = prim_expt(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, power), -27);
+
+        let args = nums(&mut mem, &[-2, 0]);
+        let power = prim_expt(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, power), 1);
+
+        let args = nums(&mut mem, &[1, isize::MAX]);
+        let power = prim_expt(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, power), 1);
+
+        let args = nums(&mut mem, &[2, 64]);
+        assert_eq!(prim_expt(&mut mem, &args), Err(EvalError::Overflow));
+
+        let cases = [
+            (2, -2, 1, 4),
+            (-2, -3, -1, 8),
+            (-1, -1, -1, 1),
+            (1, isize::MIN, 1, 1),
+        ];
+        for &(base, exp, num, den) in &cases {
+            let args = nums(&mut mem, &[base, exp]);
+            let power = prim_expt(&mut mem, &args).unwrap();
+            assert_eq!(
+                SchemeObj::read(&mem, power),
+                SchemeObj::rational(num, den).unwrap()
+            );
+        }
+
+        let args = nums(&mut mem, &[0, -1]);
+        assert_eq!(prim_expt(&mut mem, &args), Err(EvalError::DivideByZero));
+
+        let args = nums(&mut mem, &[2, -64]);
+        assert_eq!(prim_expt(&mut mem, &args), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn division() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let cases = [
+            (-7, 3, -2, -1, 2),
+            (7, -3, -2, 1, -2),
+            (-7, -3, 2, -1, -1),
+            (6, 3, 2, 0, 0),
+        ];
+        for (n, d, quotient, remainder, modulo) in cases {
+            let args = nums(&mut mem, &[n, d]);
+
+            let result = prim_quotient(&mut mem, &args).unwrap();
+            assert_eq!(read_number(&mem, result), quotient);
+            let result = prim_remainder(&mut mem, &args).unwrap();
+            assert_eq!(read_number(&mem, result), remainder);
+            let result = prim_modulo(&mut mem, &args).unwrap();
+            assert_eq!(read_number(&mem, result), modulo);
+        }
+
+        let args = nums(&mut mem, &[1, 0]);
+        assert_eq!(prim_modulo(&mut mem, &args), Err(EvalError::DivideByZero));
+
+        let args = nums(&mut mem, &[isize::MIN, -1]);
+        assert_eq!(prim_quotient(&mut mem, &args), Err(EvalError::Overflow));
+        let result = prim_remainder(&mut mem, &args).unwrap();
+        assert_eq!(read_number(&mem, result), 0);
+    }
+
+    #[test]
+    fn num_eq() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[7, 7, 7]);
+        let result = prim_num_eq(&mut mem, &args).unwrap();
+        assert!(matches!(SchemeObj::read(&mem, result), Bool(true)));
+
+        let args = nums(&mut mem, &[7, 7, 8]);
+        let result = prim_num_eq(&mut mem, &args).unwrap();
+        assert!(matches!(SchemeObj::read(&mem, result), Bool(false)));
+    }
+
+    fn read_bool(mem: &Heap, addr: usize) -> bool {
+        match SchemeObj::read(mem, addr) {
+            Bool(b) => b,
+            _ => panic!("Expected Bool at {}", addr),
+        }
+    }
+
+    #[test]
+    fn comparison_chains() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        let cases: [(Prim, &[isize], bool); 12] = [
+            (prim_lt, &[1, 2, 3], true),
+            (prim_lt, &[1, 3, 2], false),
+            (prim_lt, &[1, 1], false),
+            (prim_gt, &[3, 2, 1], true),
+            (prim_gt, &[3, 1, 2], false),
+            (prim_gt, &[1, 1], false),
+            (prim_le, &[1, 1, 2], true),
+            (prim_le, &[1, 2, 1], false),
+            (prim_ge, &[2, 2, 1], true),
+            (prim_ge, &[2, 1, 2], false),
+            (prim_lt, &[5], true),
+            (prim_ge, &[5], true),
+        ];
+        for (f, ns, expected) in cases {
+            let args = nums(&mut mem, ns);
+            let result = f(&mut mem, &args).unwrap();
+            assert_eq!(read_bool(&mem, result), expected, "{:?}", ns);
+        }
+
+        let truth = mem.alloc_obj(&Bool(true));
+        let one = mem.alloc_obj(&Number(1));
+        assert_eq!(
+            prim_lt(&mut mem, &[one, truth]),
+            Err(EvalError::NotANumber(truth))
+        );
+        assert_eq!(prim_gt(&mut mem, &[]), Err(arity_error(1, 0)));
+    }
+
+    #[test]
+    fn predicates() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let nil = mem.nil();
+        let f = mem.alloc_obj(&Bool(false));
+        let zero = mem.alloc_obj(&Number(0));
+        let one = mem.alloc_obj(&Number(1));
+        let pair = prim_cons(&mut mem, &[zero, nil]).unwrap();
+
+        let mut check = |prim: Prim, arg: usize| {
+            let result = prim(&mut mem, &[arg]).unwrap();
+            read_bool(&mem, result)
+        };
+
+        assert!(check(prim_not, f));
+        assert!(!check(prim_not, nil));
+        assert!(!check(prim_not, zero));
+
+        assert!(check(prim_is_null, nil));
+        assert!(!check(prim_is_null, pair));
+
+        assert!(check(prim_is_pair, pair));
+        assert!(!check(prim_is_pair, nil));
+
+        assert!(check(prim_is_zero, zero));
+        assert!(!check(prim_is_zero, one));
+
+        assert_eq!(
+            prim_is_zero(&mut mem, &[nil]),
+            Err(EvalError::NotANumber(nil))
+        );
+        assert_eq!(prim_not(&mut mem, &[]), Err(arity_error(1, 0)));
+    }
+
+    #[test]
+    fn car_cdr_of_cons() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[1, 2]);
+        let pair = prim_cons(&mut mem, &args).unwrap();
+
+        let first = prim_car(&mut mem, &[pair]).unwrap();
+        let rest = prim_cdr(&mut mem, &[pair]).unwrap();
+
+        assert_eq!(first, args[0]);
+        assert_eq!(rest, args[1]);
+        assert_eq!(read_number(&mem, first), 1);
+        assert_eq!(read_number(&mem, rest), 2);
+    }
+
+    #[test]
+    fn eq_and_eqv() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let mut check = |prim: Prim, a: SchemeObj, b: SchemeObj| {
+            let args = [mem.alloc_obj(&a), mem.alloc_obj(&b)];
+            let result = prim(&mut mem, &args).unwrap();
+            read_bool(&mem, result)
+        };
+
+        // Separately-allocated numbers are equivalent, but not the same.
+        assert!(!check(prim_eq, Number(5), Number(5)));
+        assert!(check(prim_eqv, Number(5), Number(5)));
+        assert!(!check(prim_eqv, Number(5), Number(6)));
+
+        // Symbols are the same whenever they have the same name.
+        assert!(check(prim_eq, Symbol(3), Symbol(3)));
+        assert!(!check(prim_eq, Symbol(3), Symbol(4)));
+
+        // Structurally equal pairs are neither.
+        let pair = || Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Nil),
+        };
+        assert!(!check(prim_eq, pair(), pair()));
+        assert!(!check(prim_eqv, pair(), pair()));
+
+        let p = mem.alloc_obj(&pair());
+        let result = prim_eq(&mut mem, &[p, p]).unwrap();
+        assert!(read_bool(&mem, result));
+    }
+
+    #[test]
+    fn set_car_cdr() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[1, 2]);
+        let pair = prim_cons(&mut mem, &args).unwrap();
+        let list = crate::list::alloc_list_addrs(&mut mem, &[pair]);
+
+        let replacements = nums(&mut mem, &[99, 100]);
+        prim_set_car(&mut mem, &[pair, replacements[0]]).unwrap();
+        prim_set_cdr(&mut mem, &[pair, replacements[1]]).unwrap();
+
+        let first = prim_car(&mut mem, &[pair]).unwrap();
+        assert_eq!(read_number(&mem, first), 99);
+
+        // The mutation is visible through the list holding the pair.
+        let shared = car(&mem, list);
+        assert_eq!(read_number(&mem, car(&mem, shared)), 99);
+        assert_eq!(read_number(&mem, cdr(&mem, shared)), 100);
+
+        assert_eq!(
+            prim_set_car(&mut mem, &[args[0], args[1]]),
+            Err(EvalError::NotAPair(args[0]))
+        );
+    }
+
+    #[test]
+    fn car_not_a_pair() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let five = mem.alloc_obj(&Number(5));
+
+        assert_eq!(prim_car(&mut mem, &[five]), Err(EvalError::NotAPair(five)));
+        assert_eq!(prim_cdr(&mut mem, &[five]), Err(EvalError::NotAPair(five)));
+        assert_eq!(prim_cons(&mut mem, &[five]), Err(arity_error(2, 1)));
+    }
+
+    #[test]
+    fn length() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Symbol(1), Symbol(2), Symbol(3)]);
+        let len = prim_length(&mut mem, &[list]).unwrap();
+        assert_eq!(read_number(&mem, len), 3);
+
+        let args = nums(&mut mem, &[1, 2]);
+        let pair = prim_cons(&mut mem, &args).unwrap();
+        assert_eq!(
+            prim_length(&mut mem, &[pair]),
+            Err(EvalError::NotAList(pair))
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        let reversed = prim_reverse(&mut mem, &[list]).unwrap();
+
+        let expected = crate::list::alloc_list(&mut mem, &[Number(3), Number(2), Number(1)]);
+        assert!(crate::data::scheme_equal(&mem, reversed, expected));
+    }
+
+    #[test]
+    fn memq() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = mem.alloc_obj(&Pair {
+            car: Box::new(Symbol(1)),
+            cdr: Box::new(Pair {
+                car: Box::new(Symbol(2)),
+                cdr: Box::new(Nil),
+            }),
+        });
+
+        let key = mem.alloc_obj(&Symbol(2));
+        let found = prim_memq(&mut mem, &[key, list]).unwrap();
+        assert_eq!(found, cdr(&mem, list));
+
+        let key = mem.alloc_obj(&Symbol(3));
+        let missing = prim_memq(&mut mem, &[key, list]).unwrap();
+        assert!(!read_bool(&mem, missing));
+    }
+
+    #[test]
+    fn assq() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let entry = |k, v| Pair {
+            car: Box::new(Symbol(k)),
+            cdr: Box::new(Number(v)),
+        };
+        let alist = mem.alloc_obj(&Pair {
+            car: Box::new(entry(1, 10)),
+            cdr: Box::new(Pair {
+                car: Box::new(entry(2, 20)),
+                cdr: Box::new(Nil),
+            }),
+        });
+
+        let key = mem.alloc_obj(&Symbol(2));
+        let found = prim_assq(&mut mem, &[key, alist]).unwrap();
+        assert_eq!(found, car(&mem, cdr(&mem, alist)));
+        assert_eq!(read_number(&mem, cdr(&mem, found)), 20);
+
+        let key = mem.alloc_obj(&Symbol(3));
+        let missing = prim_assq(&mut mem, &[key, alist]).unwrap();
+        assert!(!read_bool(&mem, missing));
+    }
+
+    #[test]
+    fn make_vector_and_fill() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[3, 0]);
+        let vector = prim_make_vector(&mut mem, &args).unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, vector),
+            Vector(vec![Number(0), Number(0), Number(0)])
+        );
+
+        let seven = mem.alloc_obj(&Number(7));
+        let result = prim_vector_fill(&mut mem, &[vector, seven]).unwrap();
+        assert_eq!(SchemeObj::read(&mem, result), Unspecified);
+        assert_eq!(
+            SchemeObj::read(&mem, vector),
+            Vector(vec![Number(7), Number(7), Number(7)])
+        );
+
+        let args = nums(&mut mem, &[2]);
+        let vector = prim_make_vector(&mut mem, &args).unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, vector),
+            Vector(vec![Unspecified, Unspecified])
+        );
+
+        let args = nums(&mut mem, &[-1]);
+        assert_eq!(
+            prim_make_vector(&mut mem, &args),
+            Err(EvalError::OutOfRange(args[0]))
+        );
+        assert_eq!(prim_make_vector(&mut mem, &[]), Err(arity_error(1, 0)));
+        assert_eq!(
+            prim_vector_fill(&mut mem, &[seven, seven]),
+            Err(EvalError::NotAVector(seven))
+        );
+    }
+
+    #[test]
+    fn make_vector_roots_fill() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        // Nothing else refers to the fill, so it would be collected (and
+        // its block perhaps reused for the vector) if the allocation
+        // didn't keep it alive.
+        let args = nums(&mut mem, &[3, 5]);
+        mem.set_gc_threshold(1.0);
+        let vector = prim_make_vector(&mut mem, &args).unwrap();
+
+        mem.verify().unwrap();
+        assert_eq!(
+            SchemeObj::read(&mem, vector),
+            Vector(vec![Number(5), Number(5), Number(5)])
+        );
+    }
+
+    #[test]
+    fn chars_and_integers() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let a = mem.alloc_obj(&Char('A'));
+        let n = prim_char_to_integer(&mut mem, &[a]).unwrap();
+        assert_eq!(read_number(&mem, n), 65);
+
+        let args = nums(&mut mem, &[65, 0x1F600]);
+        let c = prim_integer_to_char(&mut mem, &args[..1]).unwrap();
+        assert_eq!(SchemeObj::read(&mem, c), Char('A'));
+        let c = prim_integer_to_char(&mut mem, &args[1..]).unwrap();
+        assert_eq!(SchemeObj::read(&mem, c), Char('\u{1F600}'));
+
+        for &n in &[55296, 0xDFFF, 0x110000, -1] {
+            let args = nums(&mut mem, &[n]);
+            assert_eq!(
+                prim_integer_to_char(&mut mem, &args),
+                Err(EvalError::OutOfRange(args[0]))
+            );
+        }
+        assert_eq!(
+            prim_char_to_integer(&mut mem, &[n]),
+            Err(EvalError::NotAChar(n))
+        );
+        assert_eq!(
+            EvalError::OutOfRange(12).to_string(),
+            "argument out of range at 12"
+        );
+    }
+
+    #[test]
+    fn primitives_are_interned() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let car = primitive(&mut mem, "car").unwrap();
+        let car_again = primitive(&mut mem, "car").unwrap();
+        let cdr = primitive(&mut mem, "cdr").unwrap();
+
+        assert_eq!(car, car_again);
+        let same = prim_eq(&mut mem, &[car, car_again]).unwrap();
+        assert!(read_bool(&mem, same));
+        let same = prim_eq(&mut mem, &[car, cdr]).unwrap();
+        assert!(!read_bool(&mem, same));
+
+        assert_eq!(primitive(&mut mem, "no-such-primitive"), None);
+    }
+
+    #[test]
+    fn apply_spreads_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[1, 2, 3]);
+        let list = crate::list::alloc_list_addrs(&mut mem, &args);
+        let plus = primitive(&mut mem, "+").unwrap();
+        let sum = apply(&mut mem, plus, &[list]).unwrap();
+        assert_eq!(read_number(&mem, sum), 6);
+
+        let ten = mem.alloc_obj(&Number(10));
+        let minus = primitive(&mut mem, "-").unwrap();
+        let diff = apply(&mut mem, minus, &[ten, list]).unwrap();
+        assert_eq!(read_number(&mem, diff), 4);
+
+        assert_eq!(
+            apply(&mut mem, ten, &[list]),
+            Err(EvalError::NotAProcedure(ten))
+        );
+    }
+
+    #[test]
+    fn map_negations() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        let minus = primitive(&mut mem, "-").unwrap();
+        let result = map(&mut mem, minus, list).unwrap();
+
+        let expected = crate::list::alloc_list(&mut mem, &[Number(-1), Number(-2), Number(-3)]);
+        assert!(crate::data::scheme_equal(&mem, result, expected));
+
+        // A non-procedure is rejected, even if there are no elements to
+        // apply it to.
+        let nil = mem.nil();
+        assert_eq!(map(&mut mem, nil, list), Err(EvalError::NotAProcedure(nil)));
+        assert_eq!(map(&mut mem, nil, nil), Err(EvalError::NotAProcedure(nil)));
+    }
+
+    #[test]
+    fn map_survives_collection() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        let minus = primitive(&mut mem, "-").unwrap();
+        mem.push_temp_root(list);
+        mem.push_temp_root(minus);
+
+        // Every allocation now collects, so each negation (and the list
+        // being built) must be rooted.
+        mem.set_gc_threshold(1.0);
+        let result = map(&mut mem, minus, list).unwrap();
+        mem.set_gc_threshold(0.0);
+
+        let expected = crate::list::alloc_list(&mut mem, &[Number(-1), Number(-2), Number(-3)]);
+        assert!(crate::data::scheme_equal(&mem, result, expected));
+        assert_eq!(mem.pop_temp_root(), Some(minus));
+        assert_eq!(mem.pop_temp_root(), Some(list));
+    }
+
+    #[test]
+    fn for_each_errors() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Number(1), Number(2)]);
+        let minus = primitive(&mut mem, "-").unwrap();
+        let result = for_each(&mut mem, minus, list).unwrap();
+        assert!(matches!(SchemeObj::read(&mem, result), Unspecified));
+
+        let first = car(&mem, list);
+        let car_proc = primitive(&mut mem, "car").unwrap();
+        assert_eq!(
+            for_each(&mut mem, car_proc, list),
+            Err(EvalError::NotAPair(first))
+        );
+        assert_eq!(
+            for_each(&mut mem, first, list),
+            Err(EvalError::NotAProcedure(first))
+        );
+    }
+
+    #[test]
+    fn call_primitive_value() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        // Stand in for `(define f +)` by storing `+` in a pair, then
+        // fetching it back out to apply it.
+        let plus = primitive(&mut mem, "+").unwrap();
+        let nil = mem.nil();
+        let binding = cons(&mut mem, plus, nil);
+        let f = car(&mem, binding);
+
+        let args = nums(&mut mem, &[1, 2, 3]);
+        let sum = call(&mut mem, f, &args).unwrap();
+        assert_eq!(read_number(&mem, sum), 6);
+        assert_eq!(write_flat(&mem, f), "#<primitive +>");
+
+        assert_eq!(
+            call(&mut mem, sum, &args),
+            Err(EvalError::NotAProcedure(sum))
+        );
+        assert_eq!(primitive(&mut mem, "frobnicate"), None);
+    }
+
+    #[test]
+    fn display_and_newline() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+        let mut out = Vec::new();
+
+        let n = mem.alloc_obj(&Number(42));
+        let result = display(&mut mem, &mut out, &[n]).unwrap();
+        assert!(matches!(SchemeObj::read(&mem, result), Unspecified));
+        newline(&mut mem, &mut out, &[]).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "42\n");
+        assert_eq!(
+            newline(&mut mem, &mut io::sink(), &[n]),
+            Err(arity_error(0, 1))
+        );
+    }
+
+    #[test]
+    fn apply_improper_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let args = nums(&mut mem, &[1, 2]);
+        let pair = prim_cons(&mut mem, &args).unwrap();
+        let plus = primitive(&mut mem, "+").unwrap();
+
+        assert_eq!(
+            apply(&mut mem, plus, &[pair]),
+            Err(EvalError::NotAList(pair))
+        );
+        assert_eq!(apply(&mut mem, plus, &[]), Err(arity_error(1, 0)));
+    }
+
+    #[test]
+    fn cyclic_list() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = crate::list::alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+        let last = nth_tail(&mem, list, 2);
+        set_cdr(&mut mem, last, list);
+
+        let minus = primitive(&mut mem, "-").unwrap();
+        let not_a_list = Err(EvalError::NotAList(list));
+        assert_eq!(prim_length(&mut mem, &[list]), not_a_list);
+        assert_eq!(prim_reverse(&mut mem, &[list]), not_a_list);
+        assert_eq!(apply(&mut mem, minus, &[list]), not_a_list);
+        assert_eq!(map(&mut mem, minus, list), not_a_list);
+        assert_eq!(for_each(&mut mem, minus, list), not_a_list);
+    }
+
+    #[test]
+    fn not_a_number() {
+        let mut mem = Heap::new(512, Box::new(Vec::new));
+
+        let one = mem.alloc_obj(&Number(1));
+        let truth = mem.alloc_obj(&Bool(true));
+
+        assert_eq!(
+            prim_add(&mut mem, &[one, truth]),
+            Err(EvalError::NotANumber(truth))
+        );
+        assert_eq!(
+            EvalError::NotANumber(truth).to_string(),
+            format!("expected a number at {}", truth)
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Rendering heap objects as text, in the usual Scheme notation.
+
+use crate::data::{car, cdr, vector_length, vector_ref, weak_deref, SchemeObj, Tag};
+use crate::memory::{Mem, MemRead};
+use crate::prims::PRIMITIVES;
+use std::collections::{HashMap, HashSet};
+
+/// Renders the object at `addr` on a single line.
+///
+/// Any pair or vector that's reachable along more than one path
+/// (including via a cycle) is given a datum label, as with R7RS's `write-shared`: its
+/// first occurrence is written `#n=(...)`, and the rest as `#n#`. This
+/// ensures that printing a cyclic structure terminates.
+pub fn write_flat<M: Mem>(mem: &M, addr: usize) -> String {
+    Printer::new(mem, addr).flat(addr)
+}
+
+/// Renders the object at `addr`, breaking any list that doesn't fit
+/// within `width` columns across several lines. The elements of a
+/// broken list are placed one per line, aligned with its first element:
+///
+/// ```ignore
+/// (define
+///  (square x)
+///  (* x x))
+/// ```
+///
+/// Shared structure is labeled as in `write_flat`.
+pub fn pretty_print<M: Mem>(mem: &M, addr: usize, width: usize) -> String {
+    let mut out = String::new();
+    Printer::new(mem, addr).pretty(addr, width, 0, &mut out);
+    out
+}
+
+/// Tracks the datum labels used while rendering a single object.
+struct Printer<'a, M: Mem> {
+    mem: &'a M,
+    shared: HashSet<usize>,
+    labels: HashMap<usize, usize>,
+}
+
+impl<'a, M: Mem> Printer<'a, M> {
+    fn new(mem: &'a M, addr: usize) -> Printer<'a, M> {
+        Printer {
+            mem,
+            shared: shared_objects(mem, addr),
+            labels: HashMap::new(),
+        }
+    }
+
+    fn flat(&mut self, addr: usize) -> String {
+        let tag = Tag::from(self.mem.read(addr));
+        if tag != Tag::Pair && tag != Tag::Vector {
+            return write_atom(self.mem, addr);
+        }
+        if let Some(label) = self.labels.get(&addr) {
+            return format!("#{}#", label);
+        }
+
+        let prefix = self.define_label(addr);
+
+        if tag == Tag::Vector {
+            let parts: Vec<String> = (0..vector_length(self.mem, addr))
+                .map(|i| self.flat(vector_ref(self.mem, addr, i)))
+                .collect();
+
+            return format!("{}#({})", prefix, parts.join(" "));
+        }
+
+        let (elems, tail) = self.list_parts(addr);
+        let mut parts: Vec<String> = elems.iter().map(|&elem| self.flat(elem)).collect();
+
+        if let Some(tail) = tail {
+            parts.push(".".to_string());
+            parts.push(self.flat(tail));
+        }
+
+        format!("{}({})", prefix, parts.join(" "))
+    }
+
+    /// Appends the rendering of the object at `addr` to `out`, given
+    /// that it begins at column `col`. Only lists are broken: vectors
+    /// are always rendered flat.
+    ///
+    /// # Notes
+    ///
+    /// Deciding whether to break a list requires its flat width, which
+    /// we measure by rendering it flat. This is repeated for each nested
+    /// list that doesn't fit, so it's quadratic in the depth of nesting,
+    /// but that's fine for the sizes of output a person might read. If
+    /// the list doesn't fit, we discard any labels the measurement
+    /// defined, as they'll be defined again as we break it.
+    fn pretty(&mut self, addr: usize, width: usize, col: usize, out: &mut String) {
+        let labels = self.labels.clone();
+        let flat = self.flat(addr);
+
+        if col + flat.len() <= width
+            || Tag::from(self.mem.read(addr)) != Tag::Pair
+            || labels.contains_key(&addr)
+        {
+            out.push_str(&flat);
+            return;
+        }
+
+        self.labels = labels;
+
+        let prefix = self.define_label(addr);
+        let col = col + prefix.len();
+        let (elems, tail) = self.list_parts(addr);
+        let indent = " ".repeat(col + 1);
+
+        out.push_str(&prefix);
+        out.push('(');
+        for (i, &elem) in elems.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                out.push_str(&indent);
+            }
+            self.pretty(elem, width, col + 1, out);
+        }
+
+        if let Some(tail) = tail {
+            out.push('\n');
+            out.push_str(&indent);
+            out.push_str(". ");
+            self.pretty(tail, width, col + 3, out);
+        }
+
+        out.push(')');
+    }
+
+    /// Assigns the next label to the pair or vector at `addr` if it's
+    /// shared, returning the prefix (`#n=`) to write before it.
+    fn define_label(&mut self, addr: usize) -> String {
+        if !self.shared.contains(&addr) {
+            return String::new();
+        }
+
+        let label = self.labels.len();
+        self.labels.insert(addr, label);
+        format!("#{}=", label)
+    }
+
+    /// Returns the elements of the list starting at the pair `addr`,
+    /// along with its final cdr if the list is improper. A shared pair
+    /// in the cdr chain ends the list, so that it can be labeled.
+    fn list_parts(&self, addr: usize) -> (Vec<usize>, Option<usize>) {
+        let mut elems = vec![car(self.mem, addr)];
+        let mut addr = cdr(self.mem, addr);
+
+        while Tag::from(self.mem.read(addr)) == Tag::Pair && !self.shared.contains(&addr) {
+            elems.push(car(self.mem, addr));
+            addr = cdr(self.mem, addr);
+        }
+
+        match Tag::from(self.mem.read(addr)) {
+            Tag::Nil => (elems, None),
+            _ => (elems, Some(addr)),
+        }
+    }
+}
+
+/// Returns the addresses of the pairs and vectors reachable from `addr`
+/// along more than one path.
+fn shared_objects<M: Mem>(mem: &M, addr: usize) -> HashSet<usize> {
+    let mut seen = HashSet::new();
+    let mut shared = HashSet::new();
+    let mut pending = vec![addr];
+
+    while let Some(addr) = pending.pop() {
+        let tag = Tag::from(mem.read(addr));
+        if tag != Tag::Pair && tag != Tag::Vector {
+            continue;
+        }
+        if !seen.insert(addr) {
+            shared.insert(addr);
+            continue;
+        }
+
+        if tag == Tag::Vector {
+            let len = vector_length(mem, addr);
+            pending.extend((0..len).rev().map(|i| vector_ref(mem, addr, i)));
+        } else {
+            pending.push(cdr(mem, addr));
+            pending.push(car(mem, addr));
+        }
+    }
+
+    shared
+}
+
+/// Renders the object at `addr`, which is neither a pair nor a vector.
+///
+/// # Notes
+///
+/// Symbols are stored as bare indices (there's no symbol table to look
+/// their names up in), so they're rendered by index.
+fn write_atom<M: Mem>(mem: &M, addr: usize) -> String {
+    use SchemeObj::*;
+
+    match Tag::from(mem.read(addr)) {
+        Tag::WeakBox => match weak_deref(mem, addr) {
+            Some(target) => format!("#<weak {}>", target),
+            None => "#<weak>".to_string(),
+        },
+        Tag::Box => panic!("Attempted to print Box at {}", addr),
+        _ => match SchemeObj::read(mem, addr) {
+            Nil => "()".to_string(),
+            Bool(true) => "#t".to_string(),
+            Bool(false) => "#f".to_string(),
+            Number(n) => n.to_string(),
+            Symbol(i) => format!("#<symbol {}>", i),
+            Rational { num, den } => format!("{}/{}", num, den),
+            Bytevector(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+                format!("#u8({})", bytes.join(" "))
+            }
+            Primitive(i) => format!("#<primitive {}>", PRIMITIVES[i].0),
+            Char(c) => write_char(c),
+            Eof => "#<eof>".to_string(),
+            Unspecified => "#<unspecified>".to_string(),
+            Pair { .. } | Vector(_) => unreachable!(),
+        },
+    }
+}
+
+/// Renders the character `c`, as with `write`: whitespace and other
+/// control characters are written by name (or by scalar value, if they
+/// have no name).
+fn write_char(c: char) -> String {
+    match c {
+        ' ' => "#\\space".to_string(),
+        '\n' => "#\\newline".to_string(),
+        '\t' => "#\\tab".to_string(),
+        '\r' => "#\\return".to_string(),
+        '\0' => "#\\null".to_string(),
+        c if c.is_control() => format!("#\\x{:x}", c as u32),
+        c => format!("#\\{}", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{
+        cons, set_car, set_cdr,
+        SchemeObj::{self, *},
+    };
+    use crate::heap::Heap;
+    use crate::list::{alloc_list, alloc_list_addrs};
+
+    #[test]
+    fn write_atoms_and_lists() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = alloc_list(
+            &mut mem,
+            &[
+                Number(-1),
+                Bool(true),
+                SchemeObj::rational(1, 2).unwrap(),
+                Nil,
+            ],
+        );
+        assert_eq!(write_flat(&mem, list), "(-1 #t 1/2 ())");
+
+        let pair = mem.alloc_obj(&Pair {
+            car: Box::new(Number(1)),
+            cdr: Box::new(Number(2)),
+        });
+        assert_eq!(write_flat(&mem, pair), "(1 . 2)");
+    }
+
+    #[test]
+    fn write_cyclic() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let one = mem.alloc_obj(&Number(1));
+        let nil = mem.nil();
+
+        let pair = cons(&mut mem, one, nil);
+        set_cdr(&mut mem, pair, pair);
+        assert_eq!(write_flat(&mem, pair), "#0=(1 . #0#)");
+
+        let pair = cons(&mut mem, one, nil);
+        set_car(&mut mem, pair, pair);
+        assert_eq!(write_flat(&mem, pair), "#0=(#0#)");
+        assert_eq!(pretty_print(&mem, pair, 4), "#0=(#0#)");
+    }
+
+    #[test]
+    fn write_chars() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let chars = alloc_list(
+            &mut mem,
+            &[Char('A'), Char('λ'), Char(' '), Char('\n'), Char('\u{7}')],
+        );
+        assert_eq!(
+            write_flat(&mem, chars),
+            "(#\\A #\\λ #\\space #\\newline #\\x7)"
+        );
+    }
+
+    #[test]
+    fn write_vectors() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let vector = mem.alloc_obj(&Vector(vec![
+            Number(1),
+            Pair {
+                car: Box::new(Bool(false)),
+                cdr: Box::new(Nil),
+            },
+            Vector(vec![]),
+        ]));
+        assert_eq!(write_flat(&mem, vector), "#(1 (#f) #())");
+
+        let list = vector_ref(&mem, vector, 1);
+        set_car(&mut mem, list, vector);
+        assert_eq!(write_flat(&mem, vector), "#0=#(1 (#0#) #())");
+    }
+
+    #[test]
+    fn write_shared() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let shared = alloc_list(&mut mem, &[Number(1)]);
+        let list = alloc_list_addrs(&mut mem, &[shared, shared]);
+
+        assert_eq!(write_flat(&mem, list), "(#0=(1) #0#)");
+        assert_eq!(pretty_print(&mem, list, 8), "(#0=(1)\n #0#)");
+    }
+
+    #[test]
+    fn pretty_print_fits() {
+        let mut mem = Heap::new(1024, Box::new(Vec::new));
+
+        let list = alloc_list(&mut mem, &[Number(1), Number(2), Number(3)]);
+
+        assert_eq!(pretty_print(&mem, list, 80), "(1 2 3)");
+    }
+
+    #[test]
+    fn pretty_print_breaks() {
+        let mut mem = Heap::new(2048, Box::new(Vec::new));
+
+        // (1 (2 3) (4 (5 6 7)))
+        let inner = alloc_list(&mut mem, &[Number(5), Number(6), Number(7)]);
+        let four = mem.alloc_obj(&Number(4));
+        let last = alloc_list_addrs(&mut mem, &[four, inner]);
+        let middle = alloc_list(&mut mem, &[Number(2), Number(3)]);
+        let one = mem.alloc_obj(&Number(1));
+        let list = alloc_list_addrs(&mut mem, &[one, middle, last]);
+
+        assert_eq!(write_flat(&mem, list), "(1 (2 3) (4 (5 6 7)))");
+        assert_eq!(pretty_print(&mem, list, 11), "(1\n (2 3)\n (4\n  (5 6 7)))");
+    }
+}